@@ -0,0 +1,179 @@
+//! Feeds the daemon log output and measures how fast it is captured, while
+//! timing `servinel status` requests made at the same time. Two services are
+//! run in turn: one writing a steady 100k lines/s for 5s, and one writing as
+//! fast as it can.
+//!
+//! Run with `cargo bench --bench log_throughput`. Set `BENCH_LINES` to change
+//! the size of the unthrottled flood (default 1,000,000 lines).
+
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const SERVINEL: &str = env!("CARGO_BIN_EXE_servinel");
+const DEFAULT_LINES: u64 = 1_000_000;
+const STEADY_RATE: u64 = 100_000;
+const STEADY_SECS: u64 = 5;
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+fn main() {
+    // The steady service is this binary, started again by the daemon
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, flag, rate, secs] = args.as_slice()
+        && flag == "--emit"
+    {
+        emit(rate.parse().unwrap(), secs.parse().unwrap());
+        return;
+    }
+
+    let lines = std::env::var("BENCH_LINES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_LINES);
+    let exe = std::env::current_exe().expect("bench executable");
+    let root = std::env::temp_dir().join(format!("servinel-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&root).expect("create bench directory");
+    let compose = root.join("servinel-compose.yaml");
+    std::fs::write(
+        &compose,
+        format!(
+            "app_name: bench\n\
+             logging:\n  dir: ./logs\n  max_size: 1GB\n\
+             services:\n\
+             \x20 - name: idle\n    command: sleep 600\n\
+             \x20 - name: steady\n    command: \"'{}' --emit {STEADY_RATE} {STEADY_SECS}\"\n\
+             \x20 - name: flood\n    command: seq 1 {lines}\n\
+             profiles:\n  idle: [idle]\n",
+            exe.display()
+        ),
+    )
+    .expect("write compose file");
+    let compose = compose.to_str().unwrap();
+
+    servinel(&root, &["up", "--no-tui", "--profile", "idle", "-f", compose]);
+    let idle = status_latencies(&root, 20);
+    println!("status while idle:   {}", summarize(&idle));
+
+    let steady_lines = STEADY_RATE * STEADY_SECS;
+    let (elapsed, busy) = measure(&root, compose, "steady", &format!("\"line\":\"line {steady_lines}\""));
+    println!(
+        "steady {STEADY_RATE} lines/s for {STEADY_SECS}s: captured {steady_lines} lines in {elapsed:.2?}"
+    );
+    println!("status during steady: {}", summarize(&busy));
+
+    let (elapsed, busy) = measure(&root, compose, "flood", &format!("\"line\":\"{lines}\""));
+    println!(
+        "flood: captured {lines} lines in {elapsed:.2?} ({:.0} lines/s)",
+        lines as f64 / elapsed.as_secs_f64()
+    );
+    println!("status during flood:  {}", summarize(&busy));
+
+    servinel(&root, &["down", "--app", "bench"]);
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+/// Starts `service` and asks for status until its last line, matching
+/// `last_line`, is in its log file. Returns the time that took and the
+/// latency of each status request.
+fn measure(root: &Path, compose: &str, service: &str, last_line: &str) -> (Duration, Vec<Duration>) {
+    let started = Instant::now();
+    servinel(root, &["start", service, "--no-tui", "-f", compose]);
+
+    let done = Arc::new(AtomicBool::new(false));
+    let poller = {
+        let root = root.to_path_buf();
+        let done = done.clone();
+        std::thread::spawn(move || {
+            let mut latencies = Vec::new();
+            while !done.load(Ordering::Relaxed) {
+                latencies.extend(status_latencies(&root, 1));
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            latencies
+        })
+    };
+
+    let log_file = root.join("logs").join(format!("{service}.log"));
+    while !file_ends_with(&log_file, last_line) {
+        if started.elapsed() > TIMEOUT {
+            done.store(true, Ordering::Relaxed);
+            servinel(root, &["down", "--app", "bench"]);
+            panic!("{service} output not captured within {TIMEOUT:?}");
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let elapsed = started.elapsed();
+    done.store(true, Ordering::Relaxed);
+    (elapsed, poller.join().expect("status poller"))
+}
+
+/// Writes `rate` numbered lines per second for `secs` seconds, in 10ms batches.
+fn emit(rate: u64, secs: u64) {
+    let per_tick = rate / 100;
+    let started = Instant::now();
+    let mut stdout = std::io::stdout().lock();
+    let mut line = 0;
+    for tick in 0..secs * 100 {
+        for _ in 0..per_tick {
+            line += 1;
+            writeln!(stdout, "line {line}").unwrap();
+        }
+        stdout.flush().unwrap();
+        let next = Duration::from_millis((tick + 1) * 10);
+        std::thread::sleep(next.saturating_sub(started.elapsed()));
+    }
+}
+
+/// Runs the CLI against the bench's own data directory and namespace.
+fn servinel(root: &Path, args: &[&str]) {
+    let status = Command::new(SERVINEL)
+        .args(args)
+        .env("SERVINEL_HOME", root.join("home"))
+        .env("SERVINEL_NAMESPACE", "bench")
+        .stdout(Stdio::null())
+        .status()
+        .expect("run servinel");
+    assert!(status.success(), "servinel {args:?} failed");
+}
+
+fn status_latencies(root: &Path, count: usize) -> Vec<Duration> {
+    (0..count)
+        .map(|_| {
+            let started = Instant::now();
+            servinel(root, &["status", "--app", "bench"]);
+            started.elapsed()
+        })
+        .collect()
+}
+
+fn summarize(latencies: &[Duration]) -> String {
+    let mut sorted = latencies.to_vec();
+    sorted.sort();
+    let percentile = |p: usize| sorted[(sorted.len() - 1) * p / 100];
+    format!(
+        "{} requests, p50 {:.1?}, p99 {:.1?}, max {:.1?}",
+        sorted.len(),
+        percentile(50),
+        percentile(99),
+        sorted[sorted.len() - 1]
+    )
+}
+
+/// Whether the last line of `path` contains `needle`.
+fn file_ends_with(path: &PathBuf, needle: &str) -> bool {
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return false;
+    };
+    let Ok(len) = file.seek(SeekFrom::End(0)) else {
+        return false;
+    };
+    let start = len.saturating_sub(256);
+    let mut tail = String::new();
+    if file.seek(SeekFrom::Start(start)).is_err() || file.read_to_string(&mut tail).is_err() {
+        return false;
+    }
+    tail.trim_end().lines().last().is_some_and(|line| line.contains(needle))
+}
@@ -1,30 +1,55 @@
+use std::io::IsTerminal;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use clap::{Parser, Subcommand, CommandFactory};
 
 use crate::compose::load_compose;
-use crate::error::{Result, ServinelError};
-use crate::ipc::client::{ensure_daemon, request_response, stream_logs};
+use crate::error::{ErrorKind, Result, ServinelError};
+use crate::daemon::pidfile::{running_pid, running_pid_at};
+use crate::ipc::client::{
+    ensure_daemon, hello, request_response, restart_daemon, shutdown_daemon, stream_logs, Connection,
+};
 use crate::ipc::protocol::{
-    format_log_entry, Request, Response, ServiceSelector,
+    format_log_entry, format_merged_log_entry, ClientContext, ErrorResponse, LogRun, Request, Response, ServiceDetail, ServiceSelector,
+    Topic, PROTOCOL_VERSION,
 };
 use crate::tui;
-use crate::util::{find_compose_file, require_compose_file, socket_path};
+use crate::util::{
+    find_compose_file, format_duration, http_token_path, list_namespaces, parse_duration, pidfile_path, require_compose_file,
+    similar_names, socket_path, PIDFILE_NAME,
+};
 
 #[derive(Parser)]
 #[command(name = "servinel", version, about = "Service orchestrator with TUI")]
 pub struct Cli {
     #[arg(long)]
     pub verbose: bool,
+    /// Use a separate daemon, socket and state (also `SERVINEL_NAMESPACE`)
+    #[arg(long, global = true, value_parser = parse_namespace)]
+    pub namespace: Option<String>,
     #[command(subcommand)]
     pub command: Commands,
 }
 
+#[derive(Subcommand)]
+pub enum DaemonAction {
+    /// Start the background daemon if it is not running
+    Start,
+    /// Stop all services gracefully and exit the daemon
+    Stop,
+    /// Replace the daemon with this version's, restarting running services
+    Restart,
+    /// Show whether the daemon is running
+    Status,
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     Up {
-        #[arg(long)]
-        file: Option<PathBuf>,
+        /// Compose file; repeat to merge overrides on top, in order
+        #[arg(long, short = 'f')]
+        file: Vec<PathBuf>,
         #[arg(long)]
         profile: Option<String>,
         #[arg(long)]
@@ -34,8 +59,9 @@ pub enum Commands {
         service: Option<String>,
         #[arg(long)]
         profile: Option<String>,
-        #[arg(long)]
-        file: Option<PathBuf>,
+        /// Compose file; repeat to merge overrides on top, in order
+        #[arg(long, short = 'f')]
+        file: Vec<PathBuf>,
         #[arg(long)]
         no_tui: bool,
     },
@@ -45,6 +71,9 @@ pub enum Commands {
         profile: Option<String>,
         #[arg(long)]
         app: Option<String>,
+        /// Grace period before SIGKILL, overriding stop_grace_period (e.g. 5s)
+        #[arg(long, short = 't', value_parser = parse_timeout)]
+        timeout: Option<Duration>,
     },
     Restart {
         service: Option<String>,
@@ -73,17 +102,35 @@ pub enum Commands {
         tail: Option<usize>,
         #[arg(long)]
         merged: bool,
+        /// Only show one run: `current` or `previous`
+        #[arg(long)]
+        run: Option<LogRun>,
     },
     Profiles {
         #[arg(long)]
         app: Option<String>,
     },
+    /// Print status changes and service events as JSON lines
+    Events {
+        /// `status`, `lifecycle`, `health` or `metrics`; repeat for several (default: all)
+        #[arg(long = "topic")]
+        topics: Vec<Topic>,
+    },
+    /// Show a service's resolved configuration and environment
+    Inspect {
+        service: String,
+        #[arg(long)]
+        app: Option<String>,
+    },
     Dash,
     Doctor,
     #[command(hide = true)]
     DaemonClear,
-    #[command(hide = true)]
-    Daemon,
+    /// Run the daemon in the foreground, or manage the background daemon
+    Daemon {
+        #[command(subcommand)]
+        action: Option<DaemonAction>,
+    },
     Completions {
         #[arg(value_enum)]
         shell: clap_complete::Shell,
@@ -93,6 +140,9 @@ pub enum Commands {
         app: Option<String>,
         #[arg(long)]
         file: Option<PathBuf>,
+        /// Grace period before SIGKILL, overriding stop_grace_period (e.g. 5s)
+        #[arg(long, short = 't', value_parser = parse_timeout)]
+        timeout: Option<Duration>,
     },
 }
 
@@ -102,18 +152,34 @@ pub async fn execute(cli: Cli) -> Result<()> {
             std::env::set_var("SERVINEL_VERBOSE_DAEMON", "1");
         }
     }
+    // Exported so the daemon spawned on demand lands in the same namespace
+    if let Some(namespace) = &cli.namespace {
+        unsafe {
+            std::env::set_var("SERVINEL_NAMESPACE", namespace);
+        }
+    }
     match cli.command {
-        Commands::Daemon => {
+        Commands::Daemon { action: None } => {
             crate::daemon::run_daemon().await?;
         }
+        Commands::Daemon {
+            action: Some(action),
+        } => {
+            daemon_command(action).await?;
+        }
         Commands::Up {
             file,
             profile,
             no_tui,
         } => {
             ensure_daemon().await?;
-            let file = require_compose_file(file)?;
-            let request = Request::Up { file, profile };
+            let (file, overrides) = compose_files(file)?;
+            let request = Request::Up {
+                file,
+                overrides,
+                profile,
+                client: Some(ClientContext::capture()),
+            };
             handle_simple(request).await?;
             if !no_tui {
                 launch_tui().await?;
@@ -126,26 +192,37 @@ pub async fn execute(cli: Cli) -> Result<()> {
             no_tui,
         } => {
             ensure_daemon().await?;
-            let file = require_compose_file(file)?;
+            let (file, overrides) = compose_files(file)?;
             let selector = selector_from_options(service, profile, false)?;
             let request = Request::Start {
                 file: Some(file),
+                overrides,
                 app: None,
                 selector,
+                client: Some(ClientContext::capture()),
             };
             handle_simple(request).await?;
             if !no_tui {
                 launch_tui().await?;
             }
         }
-        Commands::Stop { service, profile, app } => {
+        Commands::Stop {
+            service,
+            profile,
+            app,
+            timeout,
+        } => {
             ensure_daemon().await?;
             let app = resolve_app_name(app).await?;
             let selector = selector_from_options(service, profile, true)?;
-            let request = Request::Stop { app: Some(app), selector };
+            let request = Request::Stop {
+                app: Some(app),
+                selector,
+                timeout,
+            };
             handle_simple(request).await?;
         }
-        Commands::Down { app, file } => {
+        Commands::Down { app, file, timeout } => {
             ensure_daemon().await?;
             // If file is provided, use it to resolve app name
             let app = if let Some(file) = file {
@@ -155,7 +232,10 @@ pub async fn execute(cli: Cli) -> Result<()> {
                 resolve_app_name(app).await?
             };
             
-            let request = Request::Down { app: Some(app) };
+            let request = Request::Down {
+                app: Some(app),
+                timeout,
+            };
             match request_response(&request).await? {
                 Response::Ack => {
                     println!("App stopped and removed.");
@@ -164,7 +244,7 @@ pub async fn execute(cli: Cli) -> Result<()> {
                     println!("App stopped and removed.");
                     println!("Daemon shutdown as no apps remain.");
                 }
-                Response::Error(message) => return Err(ServinelError::Usage(message)),
+                Response::Error(error) => return Err(error.into()),
                 _ => {}
             }
         }
@@ -194,7 +274,7 @@ pub async fn execute(cli: Cli) -> Result<()> {
                 Response::StatusSnapshot(snapshot) => {
                     print_status(snapshot);
                 }
-                Response::Error(message) => return Err(ServinelError::Usage(message)),
+                Response::Error(error) => return Err(error.into()),
                 _ => {}
             }
         }
@@ -205,22 +285,52 @@ pub async fn execute(cli: Cli) -> Result<()> {
             follow,
             tail,
             merged,
+            run,
         } => {
             ensure_daemon().await?;
             let app = resolve_app_name(app).await?;
             let selector = selector_from_options(service, profile, false)?;
+            let merged = merged || matches!(selector, ServiceSelector::Profile(_));
+            let width = if merged {
+                service_name_width(&app, &selector).await?
+            } else {
+                0
+            };
             let request = Request::Logs {
                 app: Some(app),
                 selector,
                 follow,
                 tail,
                 merged,
+                run,
             };
+            let color = use_color();
             stream_logs(&request, |chunk| {
-                println!("{}", format_log_entry(&chunk.entry, merged, &chunk.service));
+                if merged {
+                    println!("{}", format_merged_log_entry(&chunk.entry, &chunk.service, width, color));
+                } else {
+                    println!("{}", format_log_entry(&chunk.entry, &chunk.service));
+                }
             })
             .await?;
         }
+        Commands::Events { topics } => {
+            ensure_daemon().await?;
+            let topics = if topics.is_empty() {
+                Topic::ALL.to_vec()
+            } else {
+                topics
+            };
+            let connection = Connection::open().await?;
+            let mut events = connection.call(Request::Subscribe { topics })?;
+            while let Some(response) = events.next().await {
+                match response {
+                    Response::Event(event) => println!("{}", serde_json::to_string(&event)?),
+                    Response::Error(error) => return Err(error.into()),
+                    _ => {}
+                }
+            }
+        }
         Commands::Profiles { app } => {
             ensure_daemon().await?;
             let app = resolve_app_name(app).await?;
@@ -231,7 +341,20 @@ pub async fn execute(cli: Cli) -> Result<()> {
                         println!("{profile}");
                     }
                 }
-                Response::Error(message) => return Err(ServinelError::Usage(message)),
+                Response::Error(error) => return Err(error.into()),
+                _ => {}
+            }
+        }
+        Commands::Inspect { service, app } => {
+            ensure_daemon().await?;
+            let app = resolve_app_name(app).await?;
+            let request = Request::Inspect {
+                app: Some(app),
+                service,
+            };
+            match request_response(&request).await? {
+                Response::ServiceDetail(detail) => print_service_detail(&detail),
+                Response::Error(error) => return Err(error.into()),
                 _ => {}
             }
         }
@@ -252,11 +375,61 @@ pub async fn execute(cli: Cli) -> Result<()> {
     Ok(())
 }
 
+/// Length of the longest selected service name, so merged log lines are
+/// aligned from the first one on.
+async fn service_name_width(app: &str, selector: &ServiceSelector) -> Result<usize> {
+    let request = Request::Status {
+        app: Some(app.to_string()),
+        selector: selector.clone(),
+    };
+    match request_response(&request).await? {
+        Response::StatusSnapshot(snapshot) => Ok(snapshot
+            .apps
+            .iter()
+            .flat_map(|app| &app.services)
+            .map(|service| service.name.chars().count())
+            .max()
+            .unwrap_or(0)),
+        Response::Error(error) => Err(error.into()),
+        _ => Ok(0),
+    }
+}
+
+/// Colours output for a terminal, unless `NO_COLOR` is set.
+fn use_color() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+/// Prints `err` with a suggestion when a name was mistyped, and returns the
+/// exit status for its kind.
+pub fn report_error(err: &ServinelError) -> i32 {
+    eprintln!("Error: {err}");
+    let error = ErrorResponse::from(err);
+    let noun = match error.kind {
+        ErrorKind::AppNotFound => "apps",
+        ErrorKind::ServiceNotFound => "services",
+        ErrorKind::ProfileNotFound => "profiles",
+        _ => return error.kind.exit_code(),
+    };
+    let similar = error
+        .name
+        .as_deref()
+        .map(|name| similar_names(name, &error.candidates))
+        .unwrap_or_default();
+    match similar.as_slice() {
+        [] if error.candidates.is_empty() => {}
+        [] => eprintln!("Available {noun}: {}", error.candidates.join(", ")),
+        [one] => eprintln!("Did you mean '{one}'?"),
+        several => eprintln!("Did you mean one of: {}?", several.join(", ")),
+    }
+    error.kind.exit_code()
+}
+
 async fn handle_simple(request: Request) -> Result<()> {
     let response = request_response(&request).await?;
     match response {
         Response::Ack => Ok(()),
-        Response::Error(message) => Err(ServinelError::Usage(message)),
+        Response::Error(error) => Err(error.into()),
         _ => Ok(()),
     }
 }
@@ -272,36 +445,49 @@ async fn doctor() -> Result<()> {
     println!("Socket: {}", socket.display());
     println!("Socket exists: {}", socket_exists);
 
-    let mut system = sysinfo::System::new();
-    system.refresh_processes(sysinfo::ProcessesToUpdate::All, true);
-    let mut daemon_pids = Vec::new();
-    for process in system.processes().values() {
-        let cmd = process
-            .cmd()
-            .iter()
-            .map(|part| part.to_string_lossy().to_string())
-            .collect::<Vec<_>>()
-            .join(" ");
-        if cmd.contains("servinel") && cmd.contains("daemon") {
-            daemon_pids.push(process.pid().as_u32());
-        }
+    println!("Pidfile: {}", pidfile_path()?.display());
+    match running_pid() {
+        Some(pid) => println!("Daemon PID: {pid}"),
+        None => println!("Daemon PID: none (pidfile not locked)"),
     }
-    println!("Daemon processes: {}", daemon_pids.len());
-    if !daemon_pids.is_empty() {
-        println!("Daemon PIDs: {:?}", daemon_pids);
+    println!("Namespaces:");
+    for (name, dir) in list_namespaces()? {
+        let daemon = running_pid_at(&dir.join(PIDFILE_NAME))
+            .map(|pid| format!("daemon pid {pid}"))
+            .unwrap_or_else(|| "no daemon".to_string());
+        println!("- {:<16} {} ({})", name, dir.display(), daemon);
     }
 
-    let ping = tokio::time::timeout(
-        std::time::Duration::from_secs(1),
-        request_response(&Request::DashAttach),
-    )
-    .await;
-    match ping {
-        Ok(Ok(Response::Ack)) => println!("Daemon ping: ok"),
-        Ok(Ok(Response::Error(message))) => println!("Daemon ping error: {message}"),
-        Ok(Ok(other)) => println!("Daemon ping response: {other:?}"),
-        Ok(Err(err)) => println!("Daemon ping failed: {err}"),
-        Err(_) => println!("Daemon ping: timeout"),
+    let version = env!("CARGO_PKG_VERSION");
+    println!("CLI version: {version} (protocol {PROTOCOL_VERSION})");
+    match hello().await {
+        Ok(Some(info)) => {
+            println!(
+                "Daemon version: {} (protocol {}), pid {}, up {}",
+                info.version,
+                info.protocol_version,
+                info.pid,
+                format_duration(Duration::from_secs(info.uptime_secs))
+            );
+            println!("Daemon features: {}", info.features.join(", "));
+            match &info.http {
+                Some(addr) => println!(
+                    "HTTP API: {addr} (token in {})",
+                    http_token_path()?.display()
+                ),
+                None => println!("HTTP API: off"),
+            }
+            if info.version != version || info.protocol_version != PROTOCOL_VERSION {
+                println!(
+                    "Version mismatch: the daemon is {} but the CLI is {version}; run `servinel daemon restart`",
+                    info.version
+                );
+            }
+        }
+        Ok(None) => println!(
+            "Version mismatch: the daemon predates version reporting; run `servinel daemon restart`"
+        ),
+        Err(err) => println!("Daemon ping failed: {err}"),
     }
 
     // Show running apps/services snapshot if available
@@ -320,7 +506,7 @@ async fn doctor() -> Result<()> {
                 }
             }
         }
-        Ok(Response::Error(message)) => println!("Status error: {message}"),
+        Ok(Response::Error(error)) => println!("Status error: {}", error.message),
         Ok(other) => println!("Status response: {other:?}"),
         Err(err) => println!("Status failed: {err}"),
     }
@@ -334,29 +520,76 @@ fn daemon_clear() -> Result<()> {
         println!("Removed socket: {}", socket.display());
     }
 
-    let mut system = sysinfo::System::new();
-    system.refresh_processes(sysinfo::ProcessesToUpdate::All, true);
-    let mut killed = Vec::new();
-    for process in system.processes().values() {
-        let cmd = process
-            .cmd()
-            .iter()
-            .map(|part| part.to_string_lossy().to_string())
-            .collect::<Vec<_>>()
-            .join(" ");
-        if cmd.contains("servinel") && cmd.contains("daemon") {
-            let _ = process.kill();
-            killed.push(process.pid().as_u32());
+    match running_pid() {
+        Some(pid) => {
+            unsafe {
+                libc::kill(pid as i32, libc::SIGKILL);
+            }
+            println!("Killed daemon PID: {pid}");
         }
+        None => println!("No daemon process found."),
     }
-    if killed.is_empty() {
-        println!("No daemon processes found.");
-    } else {
-        println!("Killed daemon PIDs: {:?}", killed);
+    Ok(())
+}
+
+async fn daemon_command(action: DaemonAction) -> Result<()> {
+    match action {
+        DaemonAction::Start => {
+            ensure_daemon().await?;
+            match running_pid() {
+                Some(pid) => println!("Daemon running (pid {pid})."),
+                None => println!("Daemon running."),
+            }
+        }
+        DaemonAction::Stop => {
+            if running_pid().is_none() {
+                println!("Daemon is not running.");
+                return Ok(());
+            }
+            shutdown_daemon().await?;
+            println!("Daemon stopped.");
+        }
+        DaemonAction::Restart => {
+            restart_daemon().await?;
+            match running_pid() {
+                Some(pid) => println!("Daemon restarted (pid {pid})."),
+                None => println!("Daemon restarted."),
+            }
+        }
+        DaemonAction::Status => match running_pid() {
+            Some(pid) => {
+                println!("Daemon running (pid {pid}).");
+                if let Ok(Some(info)) = hello().await {
+                    println!("Version: {}", info.version);
+                }
+                println!("Socket: {}", socket_path()?.display());
+            }
+            None => println!("Daemon is not running."),
+        },
     }
     Ok(())
 }
 
+fn parse_namespace(value: &str) -> std::result::Result<String, String> {
+    crate::util::validate_namespace(value)?;
+    Ok(value.to_string())
+}
+
+fn parse_timeout(value: &str) -> std::result::Result<Duration, String> {
+    parse_duration(value).ok_or_else(|| format!("invalid duration '{value}'"))
+}
+
+/// Splits `-f` arguments into the base compose file and its overrides,
+/// falling back to the compose file in the current directory.
+fn compose_files(files: Vec<PathBuf>) -> Result<(PathBuf, Vec<PathBuf>)> {
+    let mut files = files.into_iter();
+    let file = require_compose_file(files.next())?;
+    let overrides = files
+        .map(crate::util::normalize_path)
+        .collect::<Result<_>>()?;
+    Ok((file, overrides))
+}
+
 fn selector_from_options(
     service: Option<String>,
     profile: Option<String>,
@@ -385,6 +618,50 @@ async fn resolve_app_name(app: Option<String>) -> Result<String> {
     ))
 }
 
+fn print_service_detail(detail: &ServiceDetail) {
+    let config = &detail.config;
+    println!("Service: {} (app {})", config.name, detail.app_name);
+    println!("Status: {}", detail.snapshot.status);
+    if let Some(pid) = detail.snapshot.pid {
+        println!("PID: {pid}");
+    }
+    if let Some(failure) = &detail.snapshot.failure {
+        println!("Failure: {failure}");
+    }
+    if let Some(ready) = detail.snapshot.time_to_ready {
+        println!("Ready after: {}", crate::util::format_duration(ready));
+    }
+    if let Some(pattern) = &config.ready_when_log {
+        println!("Ready when log matches: {pattern}");
+    }
+    if let Some(pattern) = &config.timestamp_format {
+        println!("Timestamp format: {pattern}");
+    }
+    if detail.snapshot.log_bytes_dropped > 0 {
+        println!("Log bytes dropped: {} (truncated lines and \\r rewrites)", detail.snapshot.log_bytes_dropped);
+    }
+    println!("Command: {}", config.command);
+    println!("Working directory: {}", detail.working_directory.display());
+    if !config.depends_on.is_empty() {
+        let deps: Vec<String> = config
+            .depends_on
+            .iter()
+            .map(|dep| format!("{} ({})", dep.service, dep.condition.as_str()))
+            .collect();
+        println!("Depends on: {}", deps.join(", "));
+    }
+    for file in &config.env_file {
+        println!("Env file: {}", file.display());
+    }
+    println!("Environment:");
+    if config.environment.is_empty() {
+        println!("  (none)");
+    }
+    for (key, value) in &config.environment {
+        println!("  {key}={value}");
+    }
+}
+
 fn print_status(snapshot: crate::ipc::protocol::StatusSnapshot) {
     for app in snapshot.apps {
         println!("App: {}", app.app_name);
@@ -394,20 +671,21 @@ fn print_status(snapshot: crate::ipc::protocol::StatusSnapshot) {
                 .map(|u| format!("{u}s"))
                 .unwrap_or_else(|| "-".to_string());
             let pid = service.pid.map(|p| p.to_string()).unwrap_or_else(|| "-".to_string());
-            let exit = service
-                .exit_code
-                .map(|c| c.to_string())
-                .unwrap_or_else(|| "-".to_string());
+            let exit = service.exit_description();
             println!(
-                "  {:<16} {:<10} pid={} uptime={} exit={} cpu={:.2}% mem={}KB",
+                "  {:<16} {:<10} pid={} uptime={} exit={} restarts={} cpu={:.2}% mem={}KB",
                 service.name,
                 service.status,
                 pid,
                 uptime,
                 exit,
+                service.restart_count,
                 service.metrics.cpu,
                 service.metrics.memory
             );
+            if let Some(failure) = &service.failure {
+                println!("    {failure}");
+            }
         }
     }
 }
\ No newline at end of file
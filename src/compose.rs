@@ -1,9 +1,12 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
 
 use crate::error::{Result, ServinelError};
+use crate::util::OVERRIDE_COMPOSE_FILE;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ComposeFile {
@@ -11,6 +14,33 @@ pub struct ComposeFile {
     pub services: Vec<ServiceConfig>,
     #[serde(default)]
     pub profiles: HashMap<String, Vec<String>>,
+    /// Variables inherited by every service
+    #[serde(default, with = "environment")]
+    pub environment: BTreeMap<String, String>,
+    /// Which process environment services start from
+    #[serde(default)]
+    pub inherit_env: InheritEnv,
+    /// Restricts inherited variables to these names; `PREFIX_*` matches a prefix
+    #[serde(default)]
+    pub inherit_env_vars: Option<Vec<String>>,
+    /// On-disk logs for every service, unless a service overrides them
+    #[serde(default)]
+    pub logging: Option<LogConfig>,
+    /// File each service was last defined or overridden in
+    #[serde(skip)]
+    pub provenance: HashMap<String, PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InheritEnv {
+    /// Only the variables declared in the compose file
+    None,
+    /// The environment of the CLI call that last started the app
+    #[default]
+    Client,
+    /// The environment the daemon itself was spawned with
+    Daemon,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -19,30 +49,897 @@ pub struct ServiceConfig {
     pub command: String,
     pub working_directory: Option<PathBuf>,
     #[serde(default)]
-    #[allow(dead_code)]
-    pub restart: Option<String>,
+    pub restart: Option<RestartPolicy>,
+    /// Automatic restarts allowed within `restart_window` before giving up
+    #[serde(default)]
+    pub max_restarts: Option<u32>,
+    #[serde(default, with = "duration_opt")]
+    pub restart_window: Option<Duration>,
+    #[serde(default)]
+    pub healthcheck: Option<HealthCheck>,
+    /// Regex matched against log lines; the first match marks the service ready
+    #[serde(default)]
+    pub ready_when_log: Option<String>,
+    /// How long to wait for `ready_when_log` before marking the service unhealthy
+    #[serde(default, with = "duration_opt")]
+    pub ready_timeout: Option<Duration>,
+    /// Regex whose first capture group is the time the service itself logged
+    /// a line, used to order merged logs
+    #[serde(default)]
+    pub timestamp_format: Option<String>,
+    /// Signal sent to the process group on stop (default SIGTERM)
+    #[serde(default)]
+    pub stop_signal: Option<String>,
+    /// How long to wait after `stop_signal` before sending SIGKILL
+    #[serde(default, with = "duration_opt")]
+    pub stop_grace_period: Option<Duration>,
+    /// Services that must be started before this one, and what to wait for
+    #[serde(default, with = "depends_on")]
+    pub depends_on: Vec<Dependency>,
+    /// How long to wait for `depends_on` conditions before giving up
+    #[serde(default, with = "duration_opt")]
+    pub start_timeout: Option<Duration>,
+    /// Variables for this service. After loading this holds the resolved
+    /// environment: app-level values, then `env_file`, then the service's own.
+    #[serde(default, with = "environment")]
+    pub environment: BTreeMap<String, String>,
+    /// Dotenv files, relative to the compose file
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub env_file: Vec<PathBuf>,
+    /// On-disk logs. After loading this holds the app-level settings with the
+    /// service's own on top.
+    #[serde(default)]
+    pub logging: Option<LogConfig>,
+}
+
+/// How service output is captured and where and how much is kept on disk
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LogConfig {
+    /// Directory for `<service>.log` files, relative to the compose file
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+    /// Size at which the current file is rotated, e.g. `10MB`
+    #[serde(default, with = "size_opt")]
+    pub max_size: Option<u64>,
+    /// Files kept per service, including the current one
+    #[serde(default)]
+    pub max_files: Option<u32>,
+    /// Longest line kept whole; the rest of a longer line is cut off
+    #[serde(default, with = "size_opt")]
+    pub max_line_length: Option<u64>,
+}
+
+pub const DEFAULT_LOG_MAX_SIZE: u64 = 10 << 20;
+pub const DEFAULT_LOG_MAX_FILES: u32 = 5;
+pub const DEFAULT_LOG_MAX_LINE_LENGTH: u64 = 64 << 10;
+
+impl LogConfig {
+    pub fn max_size(&self) -> u64 {
+        self.max_size.unwrap_or(DEFAULT_LOG_MAX_SIZE)
+    }
+
+    pub fn max_files(&self) -> u32 {
+        self.max_files.unwrap_or(DEFAULT_LOG_MAX_FILES).max(1)
+    }
+
+    pub fn max_line_length(&self) -> usize {
+        self.max_line_length.unwrap_or(DEFAULT_LOG_MAX_LINE_LENGTH).max(1) as usize
+    }
+
+    /// Fields set here win over those in `base`.
+    fn over(&self, base: &LogConfig) -> LogConfig {
+        LogConfig {
+            dir: self.dir.clone().or_else(|| base.dir.clone()),
+            max_size: self.max_size.or(base.max_size),
+            max_files: self.max_files.or(base.max_files),
+            max_line_length: self.max_line_length.or(base.max_line_length),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    No,
+    OnFailure,
+    Always,
+    UnlessStopped,
+}
+
+impl RestartPolicy {
+    pub fn should_restart(&self, exit_code: Option<i32>) -> bool {
+        match self {
+            RestartPolicy::No => false,
+            RestartPolicy::OnFailure => exit_code != Some(0),
+            RestartPolicy::Always | RestartPolicy::UnlessStopped => true,
+        }
+    }
+
+    /// Whether a service that is not running when the daemon starts is
+    /// brought back: `always` even after a manual stop, `unless-stopped`
+    /// only if nobody stopped it by hand.
+    pub fn resumes_with_daemon(&self, stopped_by_hand: bool) -> bool {
+        match self {
+            RestartPolicy::No | RestartPolicy::OnFailure => false,
+            RestartPolicy::Always => true,
+            RestartPolicy::UnlessStopped => !stopped_by_hand,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Dependency {
+    pub service: String,
+    #[serde(default)]
+    pub condition: DependencyCondition,
+}
+
+/// What a dependent waits for before it is started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyCondition {
+    /// The dependency's process has been spawned
+    #[default]
+    Started,
+    /// The dependency is running and, if it has a health check, passing it
+    Healthy,
+    /// The dependency has exited with status 0
+    Completed,
+}
+
+impl DependencyCondition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DependencyCondition::Started => "started",
+            DependencyCondition::Healthy => "healthy",
+            DependencyCondition::Completed => "completed",
+        }
+    }
+}
+
+/// Probe that decides whether a running service is healthy. Exactly one of
+/// `http`, `tcp` or `command` must be set.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HealthCheck {
+    /// URL on localhost that must answer a GET with a 2xx or 3xx status
+    #[serde(default)]
+    pub http: Option<String>,
+    /// Port (or `host:port`) that must accept a TCP connection
+    #[serde(default)]
+    pub tcp: Option<TcpTarget>,
+    /// Shell command that must exit with status 0
+    #[serde(default)]
+    pub command: Option<String>,
+    #[serde(default, with = "duration_opt")]
+    pub interval: Option<Duration>,
+    #[serde(default, with = "duration_opt")]
+    pub timeout: Option<Duration>,
+    /// Consecutive failures before the service is marked unhealthy
+    #[serde(default)]
+    pub retries: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum TcpTarget {
+    Port(u16),
+    Address(String),
+}
+
+impl HealthCheck {
+    pub fn interval(&self) -> Duration {
+        self.interval.unwrap_or(Duration::from_secs(5))
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout.unwrap_or(Duration::from_secs(2))
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(3).max(1)
+    }
+}
+
+pub const DEFAULT_MAX_RESTARTS: u32 = 5;
+pub const DEFAULT_RESTART_WINDOW: Duration = Duration::from_secs(60);
+pub const DEFAULT_STOP_GRACE_PERIOD: Duration = Duration::from_secs(10);
+pub const DEFAULT_START_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+impl ServiceConfig {
+    pub fn restart_policy(&self) -> RestartPolicy {
+        self.restart.unwrap_or_default()
+    }
+
+    pub fn max_restarts(&self) -> u32 {
+        self.max_restarts.unwrap_or(DEFAULT_MAX_RESTARTS)
+    }
+
+    pub fn restart_window(&self) -> Duration {
+        self.restart_window.unwrap_or(DEFAULT_RESTART_WINDOW)
+    }
+
+    pub fn stop_signal(&self) -> i32 {
+        self.stop_signal
+            .as_deref()
+            .and_then(crate::util::parse_signal)
+            .unwrap_or(libc::SIGTERM)
+    }
+
+    pub fn stop_grace_period(&self) -> Duration {
+        self.stop_grace_period.unwrap_or(DEFAULT_STOP_GRACE_PERIOD)
+    }
+
+    pub fn start_timeout(&self) -> Duration {
+        self.start_timeout.unwrap_or(DEFAULT_START_TIMEOUT)
+    }
+
+    pub fn ready_timeout(&self) -> Duration {
+        self.ready_timeout.unwrap_or(DEFAULT_READY_TIMEOUT)
+    }
+
+    /// Whether the service stays `Starting` until a probe or log line says it is ready
+    pub fn has_readiness_check(&self) -> bool {
+        self.healthcheck.is_some() || self.ready_when_log.is_some()
+    }
+
+    /// The service's current log file, when on-disk logging is enabled
+    pub fn log_file(&self) -> Option<PathBuf> {
+        let dir = self.logging.as_ref()?.dir.as_ref()?;
+        Some(dir.join(format!("{}.log", self.name)))
+    }
+
+    pub fn depends_on_service(&self, service: &str) -> bool {
+        self.depends_on.iter().any(|dep| dep.service == service)
+    }
+}
+
+/// Serde adapter for optional durations written as `10s`, `500ms` or plain seconds.
+mod duration_opt {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::util::{format_duration, parse_duration};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Seconds(f64),
+        Text(String),
+    }
+
+    pub fn serialize<S: Serializer>(value: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(duration) => serializer.serialize_str(&format_duration(*duration)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+        let raw = Option::<Raw>::deserialize(deserializer)?;
+        match raw {
+            None => Ok(None),
+            Some(Raw::Seconds(secs)) => Duration::try_from_secs_f64(secs)
+                .map(Some)
+                .map_err(serde::de::Error::custom),
+            Some(Raw::Text(text)) => parse_duration(&text)
+                .map(Some)
+                .ok_or_else(|| serde::de::Error::custom(format!("invalid duration '{text}'"))),
+        }
+    }
+}
+
+/// Serde adapter for optional byte sizes written as `10MB` or a plain number of bytes.
+mod size_opt {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::util::parse_size;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Bytes(u64),
+        Text(String),
+    }
+
+    pub fn serialize<S: Serializer>(value: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        value.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+        match Option::<Raw>::deserialize(deserializer)? {
+            None => Ok(None),
+            Some(Raw::Bytes(bytes)) => Ok(Some(bytes)),
+            Some(Raw::Text(text)) => parse_size(&text)
+                .map(Some)
+                .ok_or_else(|| serde::de::Error::custom(format!("invalid size '{text}'"))),
+        }
+    }
+}
+
+/// Serde adapter for environments written either as a map or a `KEY=VALUE` list.
+mod environment {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Map(BTreeMap<String, Option<Scalar>>),
+        List(Vec<String>),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Scalar {
+        Bool(bool),
+        Int(i64),
+        Float(f64),
+        Text(String),
+    }
+
+    pub fn serialize<S: Serializer>(value: &BTreeMap<String, String>, serializer: S) -> Result<S::Ok, S::Error> {
+        value.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error> {
+        match Raw::deserialize(deserializer)? {
+            Raw::Map(map) => Ok(map
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        None => String::new(),
+                        Some(Scalar::Bool(b)) => b.to_string(),
+                        Some(Scalar::Int(i)) => i.to_string(),
+                        Some(Scalar::Float(f)) => f.to_string(),
+                        Some(Scalar::Text(text)) => text,
+                    };
+                    (key, value)
+                })
+                .collect()),
+            Raw::List(items) => items
+                .into_iter()
+                .map(|item| match item.split_once('=') {
+                    Some((key, value)) => Ok((key.to_string(), value.to_string())),
+                    None => Err(serde::de::Error::custom(format!(
+                        "environment entry '{item}' must be KEY=VALUE"
+                    ))),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Serde adapter for `depends_on`, written either as a list of service names
+/// or as a map from service name to `{ condition: ... }`.
+mod depends_on {
+    use serde::ser::SerializeMap;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{Dependency, DependencyCondition};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        List(Vec<String>),
+        Map(serde_yaml::Mapping),
+    }
+
+    #[derive(Deserialize, Serialize)]
+    struct Options {
+        #[serde(default)]
+        condition: DependencyCondition,
+    }
+
+    pub fn serialize<S: Serializer>(value: &[Dependency], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(value.len()))?;
+        for dep in value {
+            map.serialize_entry(&dep.service, &Options { condition: dep.condition })?;
+        }
+        map.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Dependency>, D::Error> {
+        match Option::<Raw>::deserialize(deserializer)? {
+            None => Ok(Vec::new()),
+            Some(Raw::List(names)) => Ok(names
+                .into_iter()
+                .map(|service| Dependency {
+                    service,
+                    condition: DependencyCondition::default(),
+                })
+                .collect()),
+            Some(Raw::Map(map)) => map
+                .into_iter()
+                .map(|(key, value)| {
+                    let service = key
+                        .as_str()
+                        .ok_or_else(|| serde::de::Error::custom("depends_on keys must be service names"))?
+                        .to_string();
+                    let condition = if value.is_null() {
+                        DependencyCondition::default()
+                    } else {
+                        serde_yaml::from_value::<Options>(value)
+                            .map_err(serde::de::Error::custom)?
+                            .condition
+                    };
+                    Ok(Dependency { service, condition })
+                })
+                .collect(),
+        }
+    }
+}
+
+fn one_or_many<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<PathBuf>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+    Ok(match Option::<Raw>::deserialize(deserializer)? {
+        None => Vec::new(),
+        Some(Raw::One(path)) => vec![path],
+        Some(Raw::Many(paths)) => paths,
+    })
+}
+
+/// Parses dotenv content: `KEY=VALUE` lines with an optional `export` prefix,
+/// quoted values and `#` comments.
+pub fn parse_env_file(content: &str) -> Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line.split_once('=').ok_or_else(|| {
+            ServinelError::InvalidCompose(format!("line {}: expected KEY=VALUE", idx + 1))
+        })?;
+        let value = value.trim();
+        let value = if let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+            inner.replace("\\n", "\n").replace("\\\"", "\"")
+        } else if let Some(inner) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+            inner.to_string()
+        } else {
+            match value.find(" #") {
+                Some(idx) => value[..idx].trim_end().to_string(),
+                None => value.to_string(),
+            }
+        };
+        vars.push((key.trim().to_string(), value));
+    }
+    Ok(vars)
 }
 
 pub fn load_compose(path: &Path) -> Result<ComposeFile> {
-    let content = std::fs::read_to_string(path)?;
-    let mut compose: ComposeFile = serde_yaml::from_str(&content)?;
-    normalize_compose(&mut compose, path)?;
+    let env: BTreeMap<String, String> = std::env::vars().collect();
+    load_compose_files(&[path.to_path_buf()], &env)
+}
+
+/// Loads and merges compose files, later files overriding earlier ones.
+/// With a single file, `servinel-compose.override.yaml` next to it is merged
+/// on top when present. `${VAR}` references are interpolated from `env` and
+/// then from a `.env` file next to the first file.
+pub fn load_compose_files(files: &[PathBuf], env: &BTreeMap<String, String>) -> Result<ComposeFile> {
+    let base = files
+        .first()
+        .ok_or_else(|| ServinelError::InvalidCompose("no compose file given".to_string()))?;
+    let mut files = files.to_vec();
+    if files.len() == 1 {
+        let override_file = base.with_file_name(OVERRIDE_COMPOSE_FILE);
+        if override_file.is_file() {
+            files.push(override_file);
+        }
+    }
+
+    let dotenv = load_dotenv(base)?;
+    let lookup = |name: &str| env.get(name).or_else(|| dotenv.get(name)).cloned();
+    let mut merged = Mapping::new();
+    let mut provenance = HashMap::new();
+    for file in &files {
+        let fragment = load_fragment(file, &lookup, &mut Vec::new(), &mut provenance)?;
+        merge_fragment(&mut merged, fragment);
+    }
+
+    let mut compose: ComposeFile = serde_yaml::from_value(Value::Mapping(merged))?;
+    compose.provenance = provenance;
+    normalize_compose(&mut compose)?;
     validate_compose(&compose)?;
     Ok(compose)
 }
 
-fn normalize_compose(compose: &mut ComposeFile, path: &Path) -> Result<()> {
-    let base_dir = path
+/// Reads one compose file and everything it includes, merged into a single
+/// mapping. Variables are interpolated and relative paths resolved against
+/// the file's own directory before merging.
+fn load_fragment(
+    path: &Path,
+    lookup: &impl Fn(&str) -> Option<String>,
+    stack: &mut Vec<PathBuf>,
+    provenance: &mut HashMap<String, PathBuf>,
+) -> Result<Mapping> {
+    let canonical = path.canonicalize().map_err(|err| {
+        ServinelError::InvalidCompose(format!("cannot read {}: {}", path.display(), err))
+    })?;
+    if stack.contains(&canonical) {
+        let chain: Vec<String> = stack
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|file| file.display().to_string())
+            .collect();
+        return Err(ServinelError::InvalidCompose(format!(
+            "include cycle: {}",
+            chain.join(" -> ")
+        )));
+    }
+    let dir = canonical
         .parent()
-        .ok_or_else(|| ServinelError::InvalidCompose("Invalid compose path".to_string()))?;
+        .ok_or_else(|| ServinelError::InvalidCompose("Invalid compose path".to_string()))?
+        .to_path_buf();
 
-    for service in &mut compose.services {
-        if let Some(dir) = &service.working_directory {
-            if dir.is_relative() {
-                service.working_directory = Some(base_dir.join(dir));
+    let content = std::fs::read_to_string(&canonical)?;
+    let mut value: Value = serde_yaml::from_str(&content)?;
+    if value.is_null() {
+        value = Value::Mapping(Mapping::new());
+    }
+    let in_file = |err: ServinelError| match err {
+        ServinelError::InvalidCompose(message) => {
+            ServinelError::InvalidCompose(format!("{}: {}", path.display(), message))
+        }
+        other => other,
+    };
+    interpolate_compose(&mut value, lookup).map_err(in_file)?;
+    let Value::Mapping(mut fragment) = value else {
+        return Err(in_file(ServinelError::InvalidCompose(
+            "expected a mapping at the top level".to_string(),
+        )));
+    };
+    prepare_fragment(&mut fragment, &dir).map_err(in_file)?;
+
+    let includes = match fragment.remove("include") {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::String(file)) => vec![file],
+        Some(Value::Sequence(items)) => items
+            .into_iter()
+            .map(|item| match item {
+                Value::String(file) => Ok(file),
+                _ => Err(in_file(ServinelError::InvalidCompose(
+                    "include entries must be file paths".to_string(),
+                ))),
+            })
+            .collect::<Result<_>>()?,
+        Some(_) => {
+            return Err(in_file(ServinelError::InvalidCompose(
+                "include must be a path or a list of paths".to_string(),
+            )));
+        }
+    };
+
+    stack.push(canonical.clone());
+    let mut merged = Mapping::new();
+    for include in includes {
+        let included = load_fragment(&dir.join(include), lookup, stack, provenance)?;
+        merge_fragment(&mut merged, included);
+    }
+    stack.pop();
+
+    if let Some(Value::Sequence(services)) = fragment.get("services") {
+        for service in services {
+            if let Some(name) = service.get("name").and_then(Value::as_str) {
+                provenance.insert(name.to_string(), path.to_path_buf());
             }
         }
     }
+    merge_fragment(&mut merged, fragment);
+    Ok(merged)
+}
+
+/// Brings a fragment into a mergeable shape: environments become mappings,
+/// relative paths become absolute, and every service has a unique name.
+fn prepare_fragment(fragment: &mut Mapping, dir: &Path) -> Result<()> {
+    fn environment_mapping(env: &mut Value) -> Result<()> {
+        let vars = environment::deserialize(env.clone())?;
+        *env = Value::Mapping(
+            vars.into_iter()
+                .map(|(key, value)| (Value::String(key), Value::String(value)))
+                .collect(),
+        );
+        Ok(())
+    }
+
+    fn absolute(value: &mut Value, dir: &Path) {
+        if let Value::String(path) = value
+            && Path::new(path.as_str()).is_relative()
+        {
+            *path = dir.join(path.as_str()).to_string_lossy().into_owned();
+        }
+    }
+
+    if let Some(env) = fragment.get_mut("environment") {
+        environment_mapping(env)?;
+    }
+    if let Some(log_dir) = fragment.get_mut("logging").and_then(|logging| logging.get_mut("dir")) {
+        absolute(log_dir, dir);
+    }
+    let Some(Value::Sequence(services)) = fragment.get_mut("services") else {
+        return Ok(());
+    };
+    let mut names = HashSet::new();
+    for service in services {
+        let name = service
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| ServinelError::InvalidCompose("every service needs a name".to_string()))?
+            .to_string();
+        if !names.insert(name.clone()) {
+            return Err(ServinelError::InvalidCompose(format!(
+                "duplicate service name: {}",
+                name
+            )));
+        }
+        if let Some(env) = service.get_mut("environment") {
+            environment_mapping(env)?;
+        }
+        // Lists become `name: null` so later files can add conditions
+        if let Some(Value::Sequence(names)) = service.get_mut("depends_on") {
+            let deps = std::mem::take(names).into_iter().map(|name| (name, Value::Null)).collect();
+            service["depends_on"] = Value::Mapping(deps);
+        }
+        if let Some(dir_value) = service.get_mut("working_directory") {
+            absolute(dir_value, dir);
+        }
+        if let Some(log_dir) = service.get_mut("logging").and_then(|logging| logging.get_mut("dir")) {
+            absolute(log_dir, dir);
+        }
+        match service.get_mut("env_file") {
+            Some(Value::Sequence(files)) => files.iter_mut().for_each(|file| absolute(file, dir)),
+            Some(file) => absolute(file, dir),
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Merges `overlay` into `base`. Services are matched by name and merged
+/// field by field, profiles and `depends_on` are unioned, mappings such as
+/// `environment` are merged key by key, and anything else in the overlay
+/// replaces the base value.
+fn merge_fragment(base: &mut Mapping, overlay: Mapping) {
+    for (key, value) in overlay {
+        match key.as_str() {
+            Some("services") => {
+                let existing = base
+                    .entry(key)
+                    .or_insert_with(|| Value::Sequence(Vec::new()));
+                merge_services(existing, value);
+            }
+            Some("profiles") => {
+                let existing = base
+                    .entry(key)
+                    .or_insert_with(|| Value::Mapping(Mapping::new()));
+                match (existing, value) {
+                    (Value::Mapping(existing), Value::Mapping(profiles)) => {
+                        for (profile, services) in profiles {
+                            merge_union(existing.entry(profile).or_insert(Value::Null), services);
+                        }
+                    }
+                    (existing, value) => *existing = value,
+                }
+            }
+            _ => merge_value(base.entry(key).or_insert(Value::Null), value),
+        }
+    }
+}
+
+fn merge_services(base: &mut Value, overlay: Value) {
+    let Value::Sequence(overlay) = overlay else {
+        *base = overlay;
+        return;
+    };
+    let Value::Sequence(base) = base else {
+        *base = Value::Sequence(overlay);
+        return;
+    };
+    for service in overlay {
+        let name = service.get("name").and_then(Value::as_str).map(str::to_string);
+        let existing = base.iter_mut().find(|existing| {
+            name.is_some() && existing.get("name").and_then(Value::as_str) == name.as_deref()
+        });
+        match (existing, service) {
+            (Some(Value::Mapping(existing)), Value::Mapping(fields)) => {
+                for (key, value) in fields {
+                    let field = key.as_str().map(str::to_string);
+                    let entry = existing.entry(key).or_insert(Value::Null);
+                    match field.as_deref() {
+                        Some("depends_on") => merge_dependencies(entry, value),
+                        // Mixing probe types from two files never makes sense
+                        Some("healthcheck") => *entry = value,
+                        _ => merge_value(entry, value),
+                    }
+                }
+            }
+            (_, service) => base.push(service),
+        }
+    }
+}
+
+fn merge_value(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                merge_value(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Adds the overlay's dependencies; a bare name keeps any condition set earlier.
+fn merge_dependencies(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (service, options) in overlay {
+                if options.is_null() && base.contains_key(&service) {
+                    continue;
+                }
+                base.insert(service, options);
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Appends the overlay's list items that the base list does not already have.
+fn merge_union(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Sequence(base), Value::Sequence(overlay)) => {
+            for item in overlay {
+                if !base.contains(&item) {
+                    base.push(item);
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+fn load_dotenv(compose_path: &Path) -> Result<BTreeMap<String, String>> {
+    let Some(path) = compose_path.parent().map(|dir| dir.join(".env")) else {
+        return Ok(BTreeMap::new());
+    };
+    if !path.is_file() {
+        return Ok(BTreeMap::new());
+    }
+    let content = std::fs::read_to_string(&path)?;
+    let vars = parse_env_file(&content)
+        .map_err(|err| ServinelError::InvalidCompose(format!("{}: {}", path.display(), err)))?;
+    Ok(vars.into_iter().collect())
+}
+
+/// Interpolates `command`, `working_directory` and environment values in place.
+fn interpolate_compose(value: &mut Value, lookup: &impl Fn(&str) -> Option<String>) -> Result<()> {
+    fn environment_values(env: &mut Value, lookup: &impl Fn(&str) -> Option<String>) -> Result<()> {
+        match env {
+            Value::Mapping(map) => {
+                for (_, value) in map.iter_mut() {
+                    interpolate_value(value, lookup)?;
+                }
+            }
+            Value::Sequence(items) => {
+                for item in items {
+                    interpolate_value(item, lookup)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    if let Some(env) = value.get_mut("environment") {
+        environment_values(env, lookup)?;
+    }
+    if let Some(Value::Sequence(services)) = value.get_mut("services") {
+        for service in services {
+            for key in ["command", "working_directory"] {
+                if let Some(field) = service.get_mut(key) {
+                    interpolate_value(field, lookup)?;
+                }
+            }
+            if let Some(env) = service.get_mut("environment") {
+                environment_values(env, lookup)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn interpolate_value(value: &mut Value, lookup: &impl Fn(&str) -> Option<String>) -> Result<()> {
+    if let Value::String(text) = value {
+        *text = interpolate(text, lookup)?;
+    }
+    Ok(())
+}
+
+/// Expands `${VAR}`, `${VAR:-default}` and `${VAR:?message}`; `$$` is a literal `$`.
+/// Bare `$VAR` is left alone so the shell can expand it at run time.
+pub fn interpolate(input: &str, lookup: &impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(idx) = rest.find('$') {
+        output.push_str(&rest[..idx]);
+        let after = &rest[idx + 1..];
+        if let Some(stripped) = after.strip_prefix('$') {
+            output.push('$');
+            rest = stripped;
+            continue;
+        }
+        let Some(body) = after.strip_prefix('{') else {
+            output.push('$');
+            rest = after;
+            continue;
+        };
+        let end = body.find('}').ok_or_else(|| {
+            ServinelError::InvalidCompose(format!("unterminated variable reference in '{input}'"))
+        })?;
+        let expr = &body[..end];
+        rest = &body[end + 1..];
+
+        let value = if let Some((name, default)) = expr.split_once(":-") {
+            lookup(name)
+                .filter(|value| !value.is_empty())
+                .unwrap_or_else(|| default.to_string())
+        } else if let Some((name, message)) = expr.split_once(":?") {
+            match lookup(name).filter(|value| !value.is_empty()) {
+                Some(value) => value,
+                None => {
+                    let message = if message.is_empty() { "is required" } else { message };
+                    return Err(ServinelError::InvalidCompose(format!(
+                        "variable {name} is not set: {message}"
+                    )));
+                }
+            }
+        } else {
+            lookup(expr).unwrap_or_default()
+        };
+        output.push_str(&value);
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Resolves each service's environment. Paths were already made absolute
+/// while loading the file they came from.
+fn normalize_compose(compose: &mut ComposeFile) -> Result<()> {
+    for service in &mut compose.services {
+        let mut environment = compose.environment.clone();
+        for file in &service.env_file {
+            let content = std::fs::read_to_string(file).map_err(|err| {
+                ServinelError::InvalidCompose(format!(
+                    "service {}: cannot read env_file {}: {}",
+                    service_label(&compose.provenance, &service.name),
+                    file.display(),
+                    err
+                ))
+            })?;
+            let vars = parse_env_file(&content).map_err(|err| {
+                ServinelError::InvalidCompose(format!("{}: {}", file.display(), err))
+            })?;
+            environment.extend(vars);
+        }
+        environment.append(&mut service.environment);
+        service.environment = environment;
+
+        service.logging = match (&service.logging, &compose.logging) {
+            (Some(own), Some(app)) => Some(own.over(app)),
+            (own, app) => own.clone().or_else(|| app.clone()),
+        };
+    }
     Ok(())
 }
 
@@ -84,5 +981,187 @@ fn validate_compose(compose: &ComposeFile) -> Result<()> {
         }
     }
 
+    for service in &compose.services {
+        for dep in service.depends_on.iter().map(|dep| &dep.service) {
+            if dep == &service.name {
+                return Err(ServinelError::InvalidCompose(format!(
+                    "service {} cannot depend on itself",
+                    service_label(&compose.provenance, &service.name)
+                )));
+            }
+            if !service_names.contains(dep.as_str()) {
+                return Err(ServinelError::InvalidCompose(format!(
+                    "service {} depends on unknown service '{}'",
+                    service_label(&compose.provenance, &service.name),
+                    dep
+                )));
+            }
+        }
+    }
+    dependency_order(&compose.services)?;
+
+    for service in &compose.services {
+        let label = service_label(&compose.provenance, &service.name);
+        if let Some(check) = &service.healthcheck {
+            validate_healthcheck(&label, check)?;
+        }
+        if let Some(pattern) = &service.ready_when_log
+            && let Err(err) = regex::Regex::new(pattern)
+        {
+            return Err(ServinelError::InvalidCompose(format!(
+                "service {} has invalid ready_when_log: {}",
+                label, err
+            )));
+        }
+        if let Some(pattern) = &service.timestamp_format {
+            match regex::Regex::new(pattern) {
+                Ok(regex) if regex.captures_len() > 1 => {}
+                Ok(_) => {
+                    return Err(ServinelError::InvalidCompose(format!(
+                        "service {} has timestamp_format without a capture group",
+                        label
+                    )));
+                }
+                Err(err) => {
+                    return Err(ServinelError::InvalidCompose(format!(
+                        "service {} has invalid timestamp_format: {}",
+                        label, err
+                    )));
+                }
+            }
+        }
+        if let Some(signal) = &service.stop_signal
+            && crate::util::parse_signal(signal).is_none()
+        {
+            return Err(ServinelError::InvalidCompose(format!(
+                "service {} has unknown stop_signal '{}'",
+                label, signal
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Quotes a service name for error messages, adding the file it came from.
+fn service_label(provenance: &HashMap<String, PathBuf>, name: &str) -> String {
+    match provenance.get(name) {
+        Some(path) => format!("'{}' ({})", name, path.display()),
+        None => format!("'{}'", name),
+    }
+}
+
+fn validate_healthcheck(service: &str, check: &HealthCheck) -> Result<()> {
+    let probes = [
+        check.http.is_some(),
+        check.tcp.is_some(),
+        check.command.is_some(),
+    ];
+    if probes.iter().filter(|set| **set).count() != 1 {
+        return Err(ServinelError::InvalidCompose(format!(
+            "healthcheck for {} must set exactly one of http, tcp or command",
+            service
+        )));
+    }
+    if let Some(url) = &check.http
+        && let Err(reason) = crate::daemon::health::parse_http_url(url)
+    {
+        return Err(ServinelError::InvalidCompose(format!(
+            "healthcheck for {} has invalid url '{}': {} (expected http://localhost:<port>/<path>)",
+            service, url, reason
+        )));
+    }
     Ok(())
 }
+
+/// Orders services so that each one comes after the services it depends on.
+/// Ties keep declaration order; dependencies outside `services` are ignored.
+pub fn dependency_order<'a>(
+    services: impl IntoIterator<Item = &'a ServiceConfig>,
+) -> Result<Vec<String>> {
+    let services: Vec<&ServiceConfig> = services.into_iter().collect();
+    let names: HashSet<&str> = services.iter().map(|svc| svc.name.as_str()).collect();
+    let mut pending: HashMap<&str, usize> = services
+        .iter()
+        .map(|svc| {
+            let deps = svc
+                .depends_on
+                .iter()
+                .filter(|dep| names.contains(dep.service.as_str()))
+                .count();
+            (svc.name.as_str(), deps)
+        })
+        .collect();
+
+    let mut order = Vec::with_capacity(services.len());
+    while order.len() < services.len() {
+        let next = services
+            .iter()
+            .find(|svc| pending.get(svc.name.as_str()) == Some(&0))
+            .ok_or_else(|| {
+                let mut stuck: Vec<&str> = pending.keys().copied().collect();
+                stuck.sort();
+                ServinelError::InvalidCompose(format!(
+                    "dependency cycle between services: {}",
+                    stuck.join(", ")
+                ))
+            })?;
+        pending.remove(next.name.as_str());
+        for svc in &services {
+            if svc.depends_on_service(&next.name)
+                && let Some(count) = pending.get_mut(svc.name.as_str())
+            {
+                *count -= 1;
+            }
+        }
+        order.push(next.name.clone());
+    }
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(input: &str) -> Result<String> {
+        let lookup = |name: &str| match name {
+            "HOST" => Some("db.local".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        interpolate(input, &lookup)
+    }
+
+    #[test]
+    fn expands_braced_variables() {
+        assert_eq!(expand("psql -h ${HOST}").unwrap(), "psql -h db.local");
+        assert_eq!(expand("${MISSING}x").unwrap(), "x");
+    }
+
+    #[test]
+    fn uses_defaults_for_unset_or_empty_variables() {
+        assert_eq!(expand("${HOST:-localhost}").unwrap(), "db.local");
+        assert_eq!(expand("${MISSING:-localhost}").unwrap(), "localhost");
+        assert_eq!(expand("${EMPTY:-localhost}").unwrap(), "localhost");
+    }
+
+    #[test]
+    fn rejects_missing_required_variables() {
+        assert_eq!(expand("${HOST:?set HOST}").unwrap(), "db.local");
+        let err = expand("${EMPTY:?set EMPTY}").unwrap_err().to_string();
+        assert!(err.contains("EMPTY") && err.contains("set EMPTY"), "{err}");
+        let err = expand("${MISSING:?}").unwrap_err().to_string();
+        assert!(err.contains("is required"), "{err}");
+    }
+
+    #[test]
+    fn leaves_shell_syntax_alone() {
+        assert_eq!(expand("echo $HOST $$ $${HOST}").unwrap(), "echo $HOST $ ${HOST}");
+        assert_eq!(expand("cost: 5$").unwrap(), "cost: 5$");
+    }
+
+    #[test]
+    fn rejects_unterminated_references() {
+        assert!(expand("${HOST").is_err());
+    }
+}
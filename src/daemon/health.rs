@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Stdio;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::compose::{HealthCheck, TcpTarget};
+
+const LOCAL_HOSTS: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
+#[derive(Debug)]
+pub struct HttpTarget {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+/// Splits `http://localhost:8000/health` into its parts. Only plain HTTP to a
+/// loopback host is accepted; IPv6 hosts go in brackets, as in `[::1]:8000`.
+pub fn parse_http_url(url: &str) -> Result<HttpTarget, String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| "only http:// URLs are supported".to_string())?;
+    let (authority, path) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, port) = bracketed
+                .split_once(']')
+                .ok_or_else(|| format!("missing ']' in host '{authority}'"))?;
+            (host, port.strip_prefix(':'))
+        }
+        None => match authority.split_once(':') {
+            Some((_, port)) if port.contains(':') => {
+                return Err(format!(
+                    "IPv6 host '{authority}' must be in brackets, as in http://[::1]:8080/"
+                ));
+            }
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| format!("invalid port '{port}'"))?,
+        None => 80,
+    };
+    if !LOCAL_HOSTS.contains(&host) {
+        return Err(format!(
+            "host '{host}' is not local (use localhost, 127.0.0.1 or [::1])"
+        ));
+    }
+    Ok(HttpTarget {
+        host: host.to_string(),
+        port,
+        path: path.to_string(),
+    })
+}
+
+/// Runs a single probe. The caller is responsible for applying the timeout.
+pub async fn probe(
+    check: &HealthCheck,
+    workdir: &Path,
+    environment: &BTreeMap<String, String>,
+) -> Result<(), String> {
+    if let Some(url) = &check.http {
+        let target = parse_http_url(url).map_err(|reason| format!("invalid url {url}: {reason}"))?;
+        return probe_http(&target).await;
+    }
+    if let Some(target) = &check.tcp {
+        let address = match target {
+            TcpTarget::Port(port) => format!("127.0.0.1:{port}"),
+            TcpTarget::Address(address) => address.clone(),
+        };
+        return TcpStream::connect(&address)
+            .await
+            .map(|_| ())
+            .map_err(|err| format!("connect {address}: {err}"));
+    }
+    if let Some(command) = &check.command {
+        return probe_command(command, workdir, environment).await;
+    }
+    Err("no probe configured".to_string())
+}
+
+async fn probe_http(target: &HttpTarget) -> Result<(), String> {
+    let mut stream = TcpStream::connect((target.host.as_str(), target.port))
+        .await
+        .map_err(|err| format!("connect {}:{}: {err}", target.host, target.port))?;
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        target.path, target.host
+    );
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let mut head = [0u8; 64];
+    let read = stream.read(&mut head).await.map_err(|err| err.to_string())?;
+    let status_line = String::from_utf8_lossy(&head[..read]);
+    let code: u16 = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| "malformed HTTP response".to_string())?;
+    if (200..400).contains(&code) {
+        Ok(())
+    } else {
+        Err(format!("HTTP status {code}"))
+    }
+}
+
+async fn probe_command(
+    command: &str,
+    workdir: &Path,
+    environment: &BTreeMap<String, String>,
+) -> Result<(), String> {
+    let mut child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(workdir)
+        .env_clear()
+        .envs(environment)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|err| err.to_string())?;
+    // Killed if the probe is dropped on timeout, with whatever it started
+    let group = child.id().map(ProbeGroup);
+    let status = child.wait().await.map_err(|err| err.to_string())?;
+    // The group leader has been reaped, so its id may belong to someone else
+    std::mem::forget(group);
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("command exited with {status}"))
+    }
+}
+
+/// Process group of a running command probe, killed when dropped.
+struct ProbeGroup(u32);
+
+impl Drop for ProbeGroup {
+    fn drop(&mut self) {
+        unsafe {
+            libc::kill(-(self.0 as i32), libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_loopback_urls() {
+        let target = parse_http_url("http://localhost:8000/health").unwrap();
+        assert_eq!((target.host.as_str(), target.port, target.path.as_str()), ("localhost", 8000, "/health"));
+        let target = parse_http_url("http://[::1]:8080").unwrap();
+        assert_eq!((target.host.as_str(), target.port, target.path.as_str()), ("::1", 8080, "/"));
+        assert_eq!(parse_http_url("http://[::1]/").unwrap().port, 80);
+    }
+
+    #[test]
+    fn rejects_unbracketed_ipv6_and_remote_hosts() {
+        let err = parse_http_url("http://::1:8080/health").unwrap_err();
+        assert!(err.contains("brackets"), "{err}");
+        assert!(parse_http_url("http://example.com/").is_err());
+        assert!(parse_http_url("http://localhost:http/").is_err());
+        assert!(parse_http_url("https://localhost/").is_err());
+    }
+}
@@ -1,3 +1,5 @@
+pub mod health;
+pub mod pidfile;
 pub mod server;
 pub mod state;
 pub mod supervisor;
@@ -0,0 +1,89 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+use crate::error::{Result, ServinelError};
+use crate::util::pidfile_path;
+
+/// Exclusive lock on the pidfile, held for the daemon's whole lifetime. The
+/// kernel drops the lock when the process exits, however it exits. It is an
+/// open file description lock, so others can test for it without taking it.
+pub struct DaemonLock {
+    _file: File,
+}
+
+impl DaemonLock {
+    /// Takes the lock and records our pid, failing if another daemon holds it.
+    pub fn acquire() -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(pidfile_path()?)?;
+        if !try_lock(&file)? {
+            let pid = read_pid(&mut file).unwrap_or_default();
+            return Err(ServinelError::DaemonAlreadyRunning(pid));
+        }
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", std::process::id())?;
+        file.flush()?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// Pid of the running daemon, or `None` when no process holds the lock. A
+/// leftover pidfile from a crashed daemon is not locked and so is ignored.
+pub fn running_pid() -> Option<u32> {
+    running_pid_at(&pidfile_path().ok()?)
+}
+
+/// Like [`running_pid`], for the pidfile at `path`. Only tests the lock, so
+/// a daemon starting at the same time is never locked out by the probe.
+pub fn running_pid_at(path: &Path) -> Option<u32> {
+    let mut file = File::open(path).ok()?;
+    match is_locked(&file) {
+        Ok(true) => read_pid(&mut file),
+        _ => None,
+    }
+}
+
+fn try_lock(file: &File) -> Result<bool> {
+    let mut lock = whole_file_lock();
+    let result = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_OFD_SETLK, &mut lock) };
+    if result == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EAGAIN | libc::EACCES) => Ok(false),
+        _ => Err(err.into()),
+    }
+}
+
+/// Whether another open file holds the lock, without taking it.
+fn is_locked(file: &File) -> Result<bool> {
+    let mut lock = whole_file_lock();
+    let result = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_OFD_GETLK, &mut lock) };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(lock.l_type != libc::F_UNLCK as libc::c_short)
+}
+
+/// Write lock over the whole file
+fn whole_file_lock() -> libc::flock {
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = libc::F_WRLCK as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    lock
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut content = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut content).ok()?;
+    content.trim().parse().ok()
+}
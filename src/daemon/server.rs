@@ -1,47 +1,88 @@
-use futures::future::join_all;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use futures::future::join_all;
 
 use tokio::net::UnixListener;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, watch, RwLock};
 
-use crate::compose::{load_compose, ComposeFile};
-use crate::daemon::state::{uptime_seconds, DaemonState};
-use crate::daemon::supervisor::Supervisor;
+use crate::compose::{
+    load_compose_files, ComposeFile, Dependency, DependencyCondition, ServiceConfig,
+};
+use crate::daemon::state::{uptime_seconds, DaemonState, ServiceState, ServiceStatus};
+use crate::daemon::pidfile::DaemonLock;
+use crate::daemon::supervisor::{exited_event, Supervisor};
 use crate::error::{Result, ServinelError};
+use crate::ipc::http::{self, HttpAddr};
 use crate::ipc::protocol::{
-    AppSnapshot, LogChunk, ServiceSelector, ServiceSnapshot, StatusSnapshot,
+    AppSnapshot, ClientContext, DaemonInfo, Event, LogChunk, LogRun, ServiceDetail, ServiceSelector,
+    ServiceSnapshot, StatusDelta, StatusSnapshot, FEATURES, PROTOCOL_VERSION,
 };
-use crate::logs::LogEntry;
-use crate::util::{ensure_app_dir, socket_path};
+use crate::logs::{read_log_files, LogEntry, LogStore};
+use crate::util::{ensure_app_dir, format_duration, socket_path};
+
+/// Events buffered for each subscriber before it starts missing them
+const EVENT_CAPACITY: usize = 256;
 
 pub struct Daemon {
     state: Arc<RwLock<DaemonState>>,
     supervisor: Supervisor,
+    events: broadcast::Sender<Event>,
+    started_at: Instant,
+    http: Option<String>,
 }
 
 impl Daemon {
-    pub fn new() -> Self {
+    pub fn new(http: Option<String>) -> Result<Self> {
         let state = Arc::new(RwLock::new(DaemonState::load().unwrap_or_default()));
-        let supervisor = Supervisor::new(state.clone());
-        Self { state, supervisor }
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let supervisor = Supervisor::new(state.clone(), events.clone())?;
+        Ok(Self {
+            state,
+            supervisor,
+            events,
+            started_at: Instant::now(),
+            http,
+        })
+    }
+
+    pub fn hello(&self, client_version: &str, protocol_version: u32) -> DaemonInfo {
+        if client_version != env!("CARGO_PKG_VERSION") || protocol_version != PROTOCOL_VERSION {
+            tracing::warn!(client_version, protocol_version, "daemon: client is from another servinel version");
+        }
+        DaemonInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            pid: std::process::id(),
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            features: FEATURES.iter().map(|feature| feature.to_string()).collect(),
+            http: self.http.clone(),
+        }
     }
 
-    pub async fn up(&self, file: PathBuf, profile: Option<String>) -> Result<()> {
-        tracing::info!(?file, ?profile, "daemon: up start");
-        let compose = load_compose(&file)?;
+    pub async fn up(
+        &self,
+        file: PathBuf,
+        overrides: Vec<PathBuf>,
+        profile: Option<String>,
+        client: Option<ClientContext>,
+    ) -> Result<()> {
+        let client = client.unwrap_or_default();
+        let file = client.resolve_path(file);
+        tracing::info!(?file, ?overrides, ?profile, "daemon: up start");
+        let compose = load_for_client(&file, overrides, &client)?;
         let app_name = compose.app_name.clone();
         self.register_app(compose, file).await;
+        self.remember_client(&app_name, client).await;
         let selector = profile
             .map(ServiceSelector::Profile)
             .unwrap_or(ServiceSelector::All);
         let services = self.resolve_services(&app_name, &selector).await?;
-        for service in services {
-            self.supervisor.start_service(&app_name, &service).await?;
-        }
+        let started = self.start_ordered(&app_name, &services).await;
         let _ = self.state.read().await.save();
+        started?;
         tracing::info!(?app_name, "daemon: up done");
         Ok(())
     }
@@ -49,88 +90,63 @@ impl Daemon {
     pub async fn start(
         &self,
         file: Option<PathBuf>,
+        overrides: Vec<PathBuf>,
         app: Option<String>,
         selector: ServiceSelector,
+        client: Option<ClientContext>,
     ) -> Result<()> {
+        let client = client.unwrap_or_default();
         let app_name = if let Some(file) = file {
-            tracing::info!(?file, "daemon: start with file");
-            let compose = load_compose(&file)?;
+            let file = client.resolve_path(file);
+            tracing::info!(?file, ?overrides, "daemon: start with file");
+            let compose = load_for_client(&file, overrides, &client)?;
             let app_name = compose.app_name.clone();
             self.register_app(compose, file).await;
             app_name
         } else {
             self.resolve_app(app).await?
         };
+        self.remember_client(&app_name, client).await;
         let services = self.resolve_services(&app_name, &selector).await?;
-        for service in services {
-            self.supervisor.start_service(&app_name, &service).await?;
-        }
+        let started = self.start_ordered(&app_name, &services).await;
         let _ = self.state.read().await.save();
+        started?;
         Ok(())
     }
 
-    pub async fn stop(&self, app: Option<String>, selector: ServiceSelector) -> Result<()> {
+    pub async fn stop(
+        &self,
+        app: Option<String>,
+        selector: ServiceSelector,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
         let app_name = self.resolve_app(app).await?;
         let services = self.resolve_services(&app_name, &selector).await?;
-        
-        let mut futures = Vec::new();
-        for service in services {
-            let app_name = app_name.clone();
-            let supervisor = self.supervisor.clone();
-            futures.push(async move {
-                supervisor.stop_service(&app_name, &service).await
-            });
+        self.stop_ordered(&app_name, &services, timeout).await;
+        let mut state = self.state.write().await;
+        for service in &services {
+            state.set_stopped_by_hand(&app_name, service, true);
         }
-        join_all(futures).await;
-        
-        let _ = self.state.read().await.save();
+        let _ = state.save();
         Ok(())
     }
 
     pub async fn restart(&self, app: Option<String>, selector: ServiceSelector) -> Result<()> {
         let app_name = self.resolve_app(app).await?;
         let services = self.resolve_services(&app_name, &selector).await?;
-        
-        let mut stop_futures = Vec::new();
-        for service in &services {
-            let app_name = app_name.clone();
-            let service = service.clone();
-            let supervisor = self.supervisor.clone();
-            stop_futures.push(async move {
-                supervisor.stop_service(&app_name, &service).await
-            });
-        }
-        join_all(stop_futures).await;
-
-        let mut start_futures = Vec::new();
-        for service in services {
-            let app_name = app_name.clone();
-            let supervisor = self.supervisor.clone();
-            start_futures.push(async move {
-                supervisor.start_service(&app_name, &service).await
-            });
-        }
-        join_all(start_futures).await;
-        
+        self.stop_ordered(&app_name, &services, None).await;
+        let started = self.start_ordered(&app_name, &services).await;
         let _ = self.state.read().await.save();
+        started?;
         Ok(())
     }
 
-    pub async fn down(&self, app: Option<String>) -> Result<bool> {
+    pub async fn down(&self, app: Option<String>, timeout: Option<Duration>) -> Result<bool> {
         let app_name = self.resolve_app(app).await?;
         
         // Stop all services first
         let services = self.resolve_services(&app_name, &ServiceSelector::All).await?;
-        let mut stop_futures = Vec::new();
-        for service in &services {
-            let app_name = app_name.clone();
-            let service = service.clone();
-            let supervisor = self.supervisor.clone();
-            stop_futures.push(async move {
-                supervisor.stop_service(&app_name, &service).await
-            });
-        }
-        join_all(stop_futures).await;
+        self.stop_ordered(&app_name, &services, timeout).await;
 
         // Remove app from state
         {
@@ -138,19 +154,30 @@ impl Daemon {
             state.remove_app(&app_name);
             let _ = state.save();
         }
+        self.supervisor.logs().remove_app(&app_name).await;
 
         // Check if we should shutdown
         let should_shutdown = self.state.read().await.apps.is_empty();
         if should_shutdown {
-            tokio::spawn(async {
-                tokio::time::sleep(Duration::from_millis(500)).await;
-                std::process::exit(0);
-            });
+            exit_soon();
         }
 
         Ok(should_shutdown)
     }
 
+    /// Stops every app's services, keeping the apps registered, and exits.
+    pub async fn shutdown(&self) {
+        tracing::info!("daemon: shutdown requested");
+        let apps = self.state.read().await.list_apps();
+        for app in apps {
+            if let Ok(services) = self.resolve_services(&app, &ServiceSelector::All).await {
+                self.stop_ordered(&app, &services, None).await;
+            }
+        }
+        let _ = self.state.read().await.save();
+        exit_soon();
+    }
+
     pub async fn status(&self, app: Option<String>, selector: ServiceSelector) -> Result<StatusSnapshot> {
         let mut apps = Vec::new();
 
@@ -160,26 +187,14 @@ impl Daemon {
                     "--app is required for profiles or specific services".to_string(),
                 ));
             }
-            let state = self.state.read().await;
-            for app_state in state.apps.values() {
-                apps.push(build_snapshot(app_state, app_state.service_order.clone()));
-            }
-            return Ok(StatusSnapshot {
-                apps,
-                system_cpu: state.system_cpu,
-                system_memory_used: state.system_memory_used,
-                system_memory_total: state.system_memory_total,
-            });
+            return Ok(self.full_status().await);
         }
 
         let app_name = app.unwrap();
         let services = self.resolve_services(&app_name, &selector).await?;
         let state = self.state.read().await;
-        let app_state = state
-            .apps
-            .get(&app_name)
-            .ok_or_else(|| ServinelError::AppNotFound(app_name.clone()))?;
-        apps.push(build_snapshot(app_state, services));
+        let app_state = state.app(&app_name)?;
+        apps.push(build_snapshot(app_state, services, self.supervisor.logs()));
         Ok(StatusSnapshot {
             apps,
             system_cpu: state.system_cpu,
@@ -188,66 +203,295 @@ impl Daemon {
         })
     }
 
+    pub async fn full_status(&self) -> StatusSnapshot {
+        let state = self.state.read().await;
+        StatusSnapshot {
+            apps: state
+                .apps
+                .values()
+                .map(|app_state| build_snapshot(app_state, app_state.service_order.clone(), self.supervisor.logs()))
+                .collect(),
+            system_cpu: state.system_cpu,
+            system_memory_used: state.system_memory_used,
+            system_memory_total: state.system_memory_total,
+        }
+    }
+
+    /// The current status of every app, and a receiver for the events
+    /// published from then on.
+    pub async fn subscribe(&self) -> (StatusSnapshot, broadcast::Receiver<Event>) {
+        // Subscribing first means no change is missed; one already in the
+        // snapshot may arrive again, which replaying a delta tolerates
+        let receiver = self.events.subscribe();
+        (self.full_status().await, receiver)
+    }
+
     pub async fn profiles(&self, app: Option<String>) -> Result<Vec<String>> {
         let app_name = self.resolve_app(app).await?;
         let state = self.state.read().await;
-        let app_state = state
-            .apps
-            .get(&app_name)
-            .ok_or_else(|| ServinelError::AppNotFound(app_name.clone()))?;
+        let app_state = state.app(&app_name)?;
         let mut profiles: Vec<String> = app_state.profiles.keys().cloned().collect();
         profiles.sort();
         Ok(profiles)
     }
 
+    pub async fn inspect(&self, app: Option<String>, service: String) -> Result<ServiceDetail> {
+        let app_name = self.resolve_app(app).await?;
+        let state = self.state.read().await;
+        let app_state = state.app(&app_name)?;
+        let service_state = app_state.service(&service)?;
+        let snapshot = build_snapshot(app_state, vec![service.clone()], self.supervisor.logs())
+            .services
+            .pop()
+            .ok_or_else(|| ServinelError::ServiceNotFound {
+                name: service,
+                candidates: app_state.service_order.clone(),
+            })?;
+        Ok(ServiceDetail {
+            app_name: app_name.clone(),
+            config: service_state.config.clone(),
+            working_directory: app_state.service_workdir(&service_state.config),
+            snapshot,
+        })
+    }
+
     pub async fn logs(
         &self,
         app: Option<String>,
         selector: ServiceSelector,
         tail: Option<usize>,
+        run: Option<LogRun>,
+        merged: bool,
     ) -> Result<(Vec<LogChunk>, Vec<LogSubscription>)> {
+        let merged = merged || matches!(selector, ServiceSelector::Profile(_));
         let app_name = self.resolve_app(app).await?;
         let services = self.resolve_services(&app_name, &selector).await?;
         
-        // 1. Collect historical logs while holding state lock
-        let mut chunks = Vec::new();
-        {
+        // 1. Find each service's run and log settings while holding the state lock
+        let selected: Vec<(String, ServiceConfig, Option<u32>)> = {
             let state = self.state.read().await;
-            let app_state = state
-                .apps
-                .get(&app_name)
-                .ok_or_else(|| ServinelError::AppNotFound(app_name.clone()))?;
-
-            for service in &services {
-                if let Some(service_state) = app_state.services.get(service) {
-                    let entries = match tail {
-                        Some(count) => service_state.logs.tail(count),
-                        None => service_state.logs.all(),
+            let app_state = state.app(&app_name)?;
+
+            services
+                .iter()
+                .filter_map(|service| {
+                    let service_state = app_state.services.get(service)?;
+                    let run = match run {
+                        None => None,
+                        Some(LogRun::Current) => Some(service_state.run),
+                        // A service that never restarted has no previous run
+                        Some(LogRun::Previous) => Some(service_state.run.checked_sub(1).filter(|run| *run > 0)?),
                     };
-                    for entry in entries {
-                        chunks.push(LogChunk {
-                            app: app_name.clone(),
-                            service: service.clone(),
-                            entry,
-                        });
-                    }
-                }
+                    Some((service.clone(), service_state.config.clone(), run))
+                })
+                .collect()
+        };
+
+        // Log files reach further back than the in-memory buffer. Each line
+        // is ordered by the service's own timestamp when it has one; lines
+        // without one, such as stack trace continuations, stay after the
+        // stamped line before them.
+        let merged = merged && selected.len() > 1;
+        let mut keyed = Vec::new();
+        for (service, config, run) in selected {
+            let entries = persisted_logs(&config, run, tail)
+                .unwrap_or_else(|| self.supervisor.logs().entries(&app_name, &service, run, tail));
+            let mut last_source = None;
+            for entry in entries {
+                last_source = entry.source_timestamp.or(last_source);
+                let key = last_source.unwrap_or(entry.timestamp);
+                keyed.push((
+                    key,
+                    LogChunk {
+                        app: app_name.clone(),
+                        service: service.clone(),
+                        entry,
+                    },
+                ));
+            }
+        }
+        // Interleave the services' histories; `tail` then counts merged lines
+        if merged {
+            keyed.sort_by_key(|(key, _)| *key);
+            if let Some(tail) = tail {
+                keyed.drain(..keyed.len().saturating_sub(tail));
             }
         }
+        let chunks: Vec<LogChunk> = keyed.into_iter().map(|(_, chunk)| chunk).collect();
+
+        // An earlier run has ended, so there is nothing to follow
+        if run == Some(LogRun::Previous) {
+            return Ok((chunks, Vec::new()));
+        }
 
-        // 2. Collect log senders without holding state lock
-        let mut subs = Vec::new();
-        for service in services {
-            if let Some(sender) = self.supervisor.log_sender(&app_name, &service).await {
-                subs.push(LogSubscription {
+        // 2. Subscribe to each service's log channel, which outlives restarts,
+        // so followers keep receiving output from later runs
+        let subs = services
+            .into_iter()
+            .map(|service| {
+                let logs = self.supervisor.logs().service(&app_name, &service);
+                let logs = logs.lock().unwrap();
+                LogSubscription {
+                    receiver: logs.subscribe(),
+                    run: logs.buffer.current_run().unwrap_or_default(),
                     app: app_name.clone(),
-                    service: service.clone(),
-                    receiver: sender.subscribe(),
+                    service,
+                }
+            })
+            .collect();
+
+        Ok((chunks, subs))
+    }
+
+    /// Starts the selected services and their dependencies. Each service
+    /// waits in `Waiting` until its `depends_on` conditions hold, so
+    /// independent services come up concurrently. Services whose
+    /// dependencies fail or time out are marked `Failed`.
+    async fn start_ordered(&self, app: &str, services: &[String]) -> Result<()> {
+        let plan: Vec<(String, Vec<Dependency>, Duration)> = {
+            let mut state = self.state.write().await;
+            let app_state = state.app(app)?;
+            let plan: Vec<_> = app_state
+                .start_order(services)
+                .into_iter()
+                .filter_map(|name| {
+                    let service = app_state.services.get(&name)?;
+                    let waiting = !service.config.depends_on.is_empty() && !service.status.is_alive();
+                    let config = &service.config;
+                    Some((name, config.depends_on.clone(), config.start_timeout(), waiting))
+                })
+                .collect();
+            plan.into_iter()
+                .map(|(name, deps, timeout, waiting)| {
+                    if waiting {
+                        state.update_service_status(app, &name, ServiceStatus::Waiting);
+                    }
+                    state.set_failure(app, &name, None);
+                    (name, deps, timeout)
+                })
+                .collect()
+        };
+
+        let launched = watch::Sender::new(HashMap::new());
+        let results = join_all(plan.iter().map(|(service, deps, timeout)| {
+            let launched = &launched;
+            async move {
+                let outcome = match self.wait_for_dependencies(app, deps, *timeout, launched).await {
+                    Ok(()) => self
+                        .supervisor
+                        .start_service(app, service)
+                        .await
+                        .map_err(|err| err.to_string()),
+                    Err(reason) => Err(reason),
+                };
+                launched.send_modify(|launched| {
+                    launched.insert(service.clone(), outcome.is_ok());
                 });
+                if let Err(reason) = &outcome {
+                    tracing::warn!(app, service, reason, "daemon: service failed to start");
+                    let mut state = self.state.write().await;
+                    state.update_service_status(app, service, ServiceStatus::Failed);
+                    state.set_failure(app, service, Some(reason.clone()));
+                    let _ = self
+                        .events
+                        .send(exited_event(app, service, ServiceStatus::Failed, None, None));
+                }
+                outcome.map_err(|reason| format!("{service}: {reason}"))
             }
+        }))
+        .await;
+
+        let failures: Vec<String> = results.into_iter().filter_map(|result| result.err()).collect();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(ServinelError::StartFailed(failures.join("; ")))
         }
+    }
 
-        Ok((chunks, subs))
+    /// Waits until every dependency meets its condition, checking again each
+    /// time the state changes. `launched` records which services of the
+    /// current batch have been spawned (or failed).
+    async fn wait_for_dependencies(
+        &self,
+        app: &str,
+        deps: &[Dependency],
+        timeout: Duration,
+        launched: &watch::Sender<HashMap<String, bool>>,
+    ) -> std::result::Result<(), String> {
+        let deadline = Instant::now() + timeout;
+        let mut changed = self.state.read().await.changed();
+        let mut launches = launched.subscribe();
+        for dep in deps {
+            loop {
+                changed.mark_unchanged();
+                let launch = launches.borrow_and_update().get(&dep.service).copied();
+                let status = match launch {
+                    Some(false) => {
+                        return Err(format!("dependency '{}' failed to start", dep.service));
+                    }
+                    Some(true) => {
+                        let state = self.state.read().await;
+                        let Some(service) = state
+                            .apps
+                            .get(app)
+                            .and_then(|app_state| app_state.services.get(&dep.service))
+                        else {
+                            return Err(format!("dependency '{}' is gone", dep.service));
+                        };
+                        match dependency_readiness(dep.condition, service) {
+                            Readiness::Ready => break,
+                            Readiness::Failed(reason) => {
+                                return Err(format!("dependency '{}' {}", dep.service, reason));
+                            }
+                            Readiness::Waiting => service.status.as_str(),
+                        }
+                    }
+                    None => "not started",
+                };
+                let next_change = async {
+                    tokio::select! {
+                        _ = changed.changed() => {}
+                        _ = launches.changed() => {}
+                    }
+                };
+                if tokio::time::timeout_at(deadline.into(), next_change).await.is_err() {
+                    return Err(format!(
+                        "dependency '{}' was not {} within {} (status: {})",
+                        dep.service,
+                        dep.condition.as_str(),
+                        format_duration(timeout),
+                        status
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops the selected services one at a time, dependents first.
+    async fn stop_ordered(&self, app: &str, services: &[String], timeout: Option<Duration>) {
+        let order = {
+            let state = self.state.read().await;
+            match state.apps.get(app) {
+                Some(app_state) => app_state.stop_order(services),
+                None => return,
+            }
+        };
+        for service in order {
+            if let Err(err) = self.supervisor.stop_service(app, &service, timeout).await {
+                tracing::error!(?err, app, service, "daemon: stop failed");
+            }
+        }
+    }
+
+    /// Records the caller's environment so later spawns, including automatic
+    /// restarts, use it instead of the daemon's own.
+    async fn remember_client(&self, app: &str, client: ClientContext) {
+        if client.env.is_empty() {
+            return;
+        }
+        self.state.write().await.set_client_env(app, client.env);
     }
 
     pub async fn register_app(&self, compose: ComposeFile, path: PathBuf) {
@@ -280,10 +524,7 @@ impl Daemon {
         selector: &ServiceSelector,
     ) -> Result<Vec<String>> {
         let state = self.state.read().await;
-        let app_state = state
-            .apps
-            .get(app)
-            .ok_or_else(|| ServinelError::AppNotFound(app.to_string()))?;
+        let app_state = state.app(app)?;
 
         let services = match selector {
             ServiceSelector::All => app_state.service_order.clone(),
@@ -292,25 +533,71 @@ impl Daemon {
             ServiceSelector::Profile(profile) => app_state
                 .profiles
                 .get(profile)
-                .ok_or_else(|| ServinelError::ProfileNotFound(profile.clone()))?
+                .ok_or_else(|| {
+                    let mut candidates: Vec<String> = app_state.profiles.keys().cloned().collect();
+                    candidates.sort();
+                    ServinelError::ProfileNotFound {
+                        name: profile.clone(),
+                        candidates,
+                    }
+                })?
                 .clone(),
         };
 
-        let known: HashSet<_> = app_state.services.keys().cloned().collect();
         for name in &services {
-            if !known.contains(name) {
-                return Err(ServinelError::ServiceNotFound(name.clone()));
-            }
+            app_state.service(name)?;
         }
 
         Ok(services)
     }
 
+    /// Takes over services left running by a previous daemon, then starts
+    /// in the background those whose restart policy brings them back.
+    pub async fn adopt_processes(self: &Arc<Self>) {
+        let mut resume: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (app, service) in self.supervisor.adopt_processes().await {
+            resume.entry(app).or_default().push(service);
+        }
+        if resume.is_empty() {
+            return;
+        }
+        let daemon = self.clone();
+        tokio::spawn(async move {
+            for (app, services) in resume {
+                tracing::info!(app, ?services, "daemon: resuming services");
+                if let Err(err) = daemon.start_ordered(&app, &services).await {
+                    tracing::warn!(app, "daemon: cannot resume services: {err}");
+                }
+            }
+            let _ = daemon.state.read().await.save();
+        });
+    }
+
     pub async fn tick_loop(&self) {
         let mut interval = tokio::time::interval(Duration::from_millis(800));
         loop {
             interval.tick().await;
             let _ = self.supervisor.refresh().await;
+            self.supervisor.check_health().await;
+        }
+    }
+
+    /// Publishes status deltas and metrics each time the state changes, by
+    /// comparing snapshots from before and after. Lifecycle and health events
+    /// are sent by the supervisor as the transitions happen.
+    pub async fn publish_loop(&self) {
+        let mut changed = self.state.read().await.changed();
+        let mut last = self.full_status().await;
+        loop {
+            if changed.changed().await.is_err() {
+                return;
+            }
+            let current = self.full_status().await;
+            for event in status_events(&last, &current) {
+                // Fails only while nobody is subscribed
+                let _ = self.events.send(event);
+            }
+            last = current;
         }
     }
 }
@@ -319,17 +606,69 @@ pub struct LogSubscription {
     pub app: String,
     pub service: String,
     pub receiver: tokio::sync::broadcast::Receiver<LogEntry>,
+    /// Run of the last line passed on
+    pub run: u32,
+}
+
+enum Readiness {
+    Ready,
+    Waiting,
+    Failed(String),
+}
+
+/// Checks a spawned dependency against a `depends_on` condition. A dependency
+/// that has exited and will not be restarted can no longer become ready.
+fn dependency_readiness(condition: DependencyCondition, service: &ServiceState) -> Readiness {
+    let exited = matches!(service.status, ServiceStatus::Exited | ServiceStatus::Stopped);
+    let restarts = service.config.restart_policy().should_restart(service.exit_code);
+    match condition {
+        DependencyCondition::Started => Readiness::Ready,
+        DependencyCondition::Healthy => match service.status {
+            ServiceStatus::Running => Readiness::Ready,
+            _ if exited && !restarts => Readiness::Failed("exited before becoming healthy".to_string()),
+            _ => Readiness::Waiting,
+        },
+        DependencyCondition::Completed => match service.exit_code {
+            Some(0) if exited => Readiness::Ready,
+            code if exited && !restarts => Readiness::Failed(format!(
+                "did not complete successfully (exit {})",
+                code.map(|code| code.to_string())
+                    .or_else(|| service.exit_signal.map(crate::util::signal_name))
+                    .unwrap_or_else(|| "-".to_string())
+            )),
+            _ => Readiness::Waiting,
+        },
+    }
 }
 
-fn build_snapshot(app_state: &crate::daemon::state::AppState, services: Vec<String>) -> AppSnapshot {
+/// Loads `file` with its overrides, resolving paths against the client's cwd
+/// and interpolating with the client's variables when it sent any, falling
+/// back to the daemon's own environment.
+fn load_for_client(
+    file: &std::path::Path,
+    overrides: Vec<PathBuf>,
+    client: &ClientContext,
+) -> Result<ComposeFile> {
+    let files: Vec<PathBuf> = std::iter::once(file.to_path_buf())
+        .chain(overrides.into_iter().map(|path| client.resolve_path(path)))
+        .collect();
+    if client.env.is_empty() {
+        let env: BTreeMap<String, String> = std::env::vars().collect();
+        load_compose_files(&files, &env)
+    } else {
+        load_compose_files(&files, &client.env)
+    }
+}
+
+fn build_snapshot(
+    app_state: &crate::daemon::state::AppState,
+    services: Vec<String>,
+    logs: &LogStore,
+) -> AppSnapshot {
     let mut service_snapshots = Vec::new();
     for name in services {
         if let Some(service) = app_state.services.get(&name) {
-            let uptime_secs = if matches!(
-                service.status,
-                crate::daemon::state::ServiceStatus::Running
-                    | crate::daemon::state::ServiceStatus::Starting
-            ) {
+            let uptime_secs = if service.status.is_alive() {
                 uptime_seconds(service.started_at)
             } else {
                 None
@@ -341,6 +680,14 @@ fn build_snapshot(app_state: &crate::daemon::state::AppState, services: Vec<Stri
                 pid: service.pid,
                 uptime_secs,
                 exit_code: service.exit_code,
+                exit_signal: service.exit_signal,
+                restart_count: service.restart_count,
+                failure: match service.status {
+                    ServiceStatus::Failed => service.failure.clone(),
+                    _ => None,
+                },
+                time_to_ready: service.time_to_ready,
+                log_bytes_dropped: logs.bytes_dropped(&app_state.app_name, &name),
                 metrics: service.metrics.clone(),
             });
         }
@@ -351,17 +698,121 @@ fn build_snapshot(app_state: &crate::daemon::state::AppState, services: Vec<Stri
     }
 }
 
+/// Events describing how the status went from `last` to `current`: a delta
+/// of the apps that changed, followed by the metrics of their services.
+fn status_events(last: &StatusSnapshot, current: &StatusSnapshot) -> Vec<Event> {
+    let mut events = Vec::new();
+    let mut changed_apps = Vec::new();
+    for app in &current.apps {
+        let previous = last.apps.iter().find(|known| known.app_name == app.app_name);
+        if previous == Some(app) {
+            continue;
+        }
+        for service in &app.services {
+            let before = previous.and_then(|previous| {
+                previous
+                    .services
+                    .iter()
+                    .find(|known| known.name == service.name)
+            });
+            events.extend(metrics_event(&app.app_name, before, service));
+        }
+        changed_apps.push(app.clone());
+    }
+    let removed_apps: Vec<String> = last
+        .apps
+        .iter()
+        .filter(|known| !current.apps.iter().any(|app| app.app_name == known.app_name))
+        .map(|app| app.app_name.clone())
+        .collect();
+    let system_changed = (last.system_cpu, last.system_memory_used, last.system_memory_total)
+        != (current.system_cpu, current.system_memory_used, current.system_memory_total);
+    if !changed_apps.is_empty() || !removed_apps.is_empty() || system_changed {
+        events.insert(
+            0,
+            Event::Delta(StatusDelta {
+                apps: changed_apps,
+                removed_apps,
+                system_cpu: current.system_cpu,
+                system_memory_used: current.system_memory_used,
+                system_memory_total: current.system_memory_total,
+            }),
+        );
+    }
+    events
+}
+
+fn metrics_event(app: &str, before: Option<&ServiceSnapshot>, after: &ServiceSnapshot) -> Option<Event> {
+    (after.is_alive() && before.is_none_or(|before| before.metrics != after.metrics)).then(|| Event::Metrics {
+        app: app.to_string(),
+        service: after.name.clone(),
+        metrics: after.metrics.clone(),
+    })
+}
+
+/// Reads a service's history from its log files, which reach further back
+/// than the in-memory buffer. `None` when on-disk logging is off or unreadable.
+fn persisted_logs(config: &ServiceConfig, run: Option<u32>, tail: Option<usize>) -> Option<Vec<LogEntry>> {
+    let path = config.log_file()?;
+    let max_files = config.logging.as_ref()?.max_files();
+    match read_log_files(&path, max_files, run, tail) {
+        Ok(entries) => Some(entries),
+        Err(err) => {
+            tracing::warn!(?err, ?path, "daemon: cannot read log files");
+            None
+        }
+    }
+}
+
+/// Exits shortly, giving the current response time to reach the client.
+fn exit_soon() {
+    tokio::spawn(async {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        std::process::exit(0);
+    });
+}
+
 pub async fn run_daemon() -> Result<()> {
     ensure_app_dir()?;
+    // Held until the process exits; a second daemon fails here before it can
+    // touch the socket of the running one
+    let _lock = DaemonLock::acquire()?;
     let socket = socket_path()?;
     if socket.exists() {
         let _ = std::fs::remove_file(&socket);
     }
     let listener = UnixListener::bind(socket)?;
-    let daemon = Arc::new(Daemon::new());
+    let http = match HttpAddr::from_env() {
+        Ok(Some(addr)) => match http::bind(&addr).await {
+            Ok(listener) => Some((addr, listener)),
+            Err(err) => {
+                tracing::error!(%addr, "daemon: cannot start the HTTP API: {err}");
+                None
+            }
+        },
+        Ok(None) => None,
+        Err(err) => {
+            tracing::error!("daemon: {err}");
+            None
+        }
+    };
+    let daemon = Arc::new(Daemon::new(http.as_ref().map(|(addr, _)| addr.to_string()))?);
+    if let Some((_, listener)) = http {
+        let daemon = daemon.clone();
+        tokio::spawn(async move {
+            if let Err(err) = http::serve(listener, daemon).await {
+                tracing::error!("daemon: HTTP API stopped: {err}");
+            }
+        });
+    }
+    daemon.adopt_processes().await;
     let daemon_clone = daemon.clone();
     tokio::spawn(async move {
         daemon_clone.tick_loop().await;
     });
+    let daemon_clone = daemon.clone();
+    tokio::spawn(async move {
+        daemon_clone.publish_loop().await;
+    });
     crate::ipc::server::serve(listener, daemon).await
 }
@@ -1,32 +1,48 @@
-use std::collections::HashMap;
-use std::time::SystemTime;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
 use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
 
-use crate::compose::{ComposeFile, ServiceConfig};
-use crate::logs::{LogBuffer, LogEntry};
+use crate::compose::{dependency_order, ComposeFile, InheritEnv, ServiceConfig};
+use crate::error::{Result, ServinelError};
 use crate::metrics::ServiceMetrics;
 
-const LOG_BUFFER_CAPACITY: usize = 1000;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ServiceStatus {
+    /// Not started yet because its dependencies are not ready
+    Waiting,
     Starting,
     Running,
     Stopped,
     Unhealthy,
     Exited,
+    /// Never started because its dependencies did not become ready
+    Failed,
 }
 
 impl ServiceStatus {
     pub fn as_str(&self) -> &'static str {
         match self {
+            ServiceStatus::Waiting => "waiting",
             ServiceStatus::Starting => "starting",
             ServiceStatus::Running => "running",
             ServiceStatus::Stopped => "stopped",
             ServiceStatus::Unhealthy => "unhealthy",
             ServiceStatus::Exited => "exited",
+            ServiceStatus::Failed => "failed",
         }
     }
+
+    /// Whether the service has a live process behind it
+    pub fn is_alive(&self) -> bool {
+        matches!(
+            self,
+            ServiceStatus::Starting | ServiceStatus::Running | ServiceStatus::Unhealthy
+        )
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,10 +50,32 @@ pub struct ServiceState {
     pub config: ServiceConfig,
     pub status: ServiceStatus,
     pub pid: Option<u32>,
+    /// Kernel start time of `pid`, used to recognise it after a daemon restart
+    #[serde(default)]
+    pub process_start: Option<u64>,
     pub started_at: Option<SystemTime>,
     pub exit_code: Option<i32>,
-    #[serde(skip)]
-    pub logs: LogBuffer,
+    /// Signal that terminated the last run, if it did not exit normally
+    #[serde(default)]
+    pub exit_signal: Option<i32>,
+    /// Automatic restarts since the service was last started by hand
+    #[serde(default)]
+    pub restart_count: u32,
+    /// Why the last start attempt failed
+    #[serde(default)]
+    pub failure: Option<String>,
+    /// How long the current run took to pass its readiness check
+    #[serde(default)]
+    pub time_to_ready: Option<Duration>,
+    /// Number of the current run; each start of the service begins a new one
+    #[serde(default)]
+    pub run: u32,
+    /// Stopped with `servinel stop` rather than by the daemon shutting down
+    #[serde(default)]
+    pub stopped_by_hand: bool,
+    /// Bytes of the current run's stdout and stderr files read into the logs
+    #[serde(default)]
+    pub output_offsets: [u64; 2],
     #[serde(default)]
     pub metrics: ServiceMetrics,
 }
@@ -50,6 +88,104 @@ pub struct AppState {
     pub services: HashMap<String, ServiceState>,
     /// Preserves declaration order from the compose file
     pub service_order: Vec<String>,
+    #[serde(default)]
+    pub inherit_env: InheritEnv,
+    #[serde(default)]
+    pub inherit_env_vars: Option<Vec<String>>,
+    /// Variables inherited from the client that last started this app. Saved
+    /// with the rest of the state, so services restarted by a new daemon
+    /// still get them
+    #[serde(default)]
+    pub client_env: Option<BTreeMap<String, String>>,
+}
+
+impl AppState {
+    pub fn service(&self, name: &str) -> Result<&ServiceState> {
+        self.services.get(name).ok_or_else(|| ServinelError::ServiceNotFound {
+            name: name.to_string(),
+            candidates: self.service_order.clone(),
+        })
+    }
+
+    /// Directory the service runs in, defaulting to the compose file's directory.
+    pub fn service_workdir(&self, config: &ServiceConfig) -> std::path::PathBuf {
+        config.working_directory.clone().unwrap_or_else(|| {
+            self.compose_path
+                .parent()
+                .map(|dir| dir.to_path_buf())
+                .unwrap_or_else(|| std::env::current_dir().unwrap_or_default())
+        })
+    }
+
+    /// Full environment for a spawned service: the inherited base chosen by
+    /// `inherit_env`, filtered by `inherit_env_vars`, then the service's own.
+    pub fn service_env(&self, config: &ServiceConfig) -> BTreeMap<String, String> {
+        let base: BTreeMap<String, String> = match self.inherit_env {
+            InheritEnv::None => BTreeMap::new(),
+            InheritEnv::Daemon => std::env::vars().collect(),
+            // An app no client has started yet, e.g. through the HTTP API
+            InheritEnv::Client => self
+                .client_env
+                .clone()
+                .unwrap_or_else(|| std::env::vars().collect()),
+        };
+        let mut env: BTreeMap<String, String> = base
+            .into_iter()
+            .filter(|(key, _)| self.inherits_var(key))
+            .collect();
+        env.extend(config.environment.clone());
+        env
+    }
+
+    fn inherits_var(&self, key: &str) -> bool {
+        let Some(allowed) = &self.inherit_env_vars else {
+            return true;
+        };
+        allowed.iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => key.starts_with(prefix),
+            None => pattern == key,
+        })
+    }
+
+    /// Returns `selected` plus everything it transitively depends on,
+    /// ordered so dependencies start first.
+    pub fn start_order(&self, selected: &[String]) -> Vec<String> {
+        let mut needed: HashSet<&str> = HashSet::new();
+        let mut stack: Vec<&str> = selected.iter().map(String::as_str).collect();
+        while let Some(name) = stack.pop() {
+            if !needed.insert(name) {
+                continue;
+            }
+            if let Some(svc) = self.services.get(name) {
+                stack.extend(svc.config.depends_on.iter().map(|dep| dep.service.as_str()));
+            }
+        }
+        self.ordered(|name| needed.contains(name))
+    }
+
+    /// Returns `selected` ordered so dependents stop before their dependencies.
+    pub fn stop_order(&self, selected: &[String]) -> Vec<String> {
+        let mut order = self.ordered(|name| selected.iter().any(|s| s == name));
+        order.reverse();
+        order
+    }
+
+    fn ordered(&self, include: impl Fn(&str) -> bool) -> Vec<String> {
+        let configs = self
+            .service_order
+            .iter()
+            .filter(|name| include(name))
+            .filter_map(|name| self.services.get(name))
+            .map(|svc| &svc.config);
+        // Cycles are rejected when the compose file is loaded
+        dependency_order(configs).unwrap_or_else(|_| {
+            self.service_order
+                .iter()
+                .filter(|name| include(name))
+                .cloned()
+                .collect()
+        })
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -61,23 +197,84 @@ pub struct DaemonState {
     pub system_memory_used: u64,
     #[serde(default)]
     pub system_memory_total: u64,
+    /// Signalled whenever anything shown in a status snapshot changes
+    #[serde(skip)]
+    changed: watch::Sender<()>,
 }
 
 impl DaemonState {
+    pub fn app(&self, name: &str) -> Result<&AppState> {
+        self.apps.get(name).ok_or_else(|| {
+            let mut candidates = self.list_apps();
+            candidates.sort();
+            ServinelError::AppNotFound {
+                name: name.to_string(),
+                candidates,
+            }
+        })
+    }
+
+    pub fn changed(&self) -> watch::Receiver<()> {
+        self.changed.subscribe()
+    }
+
+    fn service_mut(&mut self, app: &str, service: &str) -> Option<&mut ServiceState> {
+        self.apps.get_mut(app)?.services.get_mut(service)
+    }
+
+    /// Sets a field shown in status snapshots, signalling `changed` only if
+    /// the value is different.
+    fn set_shown<T: PartialEq>(
+        &mut self,
+        app: &str,
+        service: &str,
+        field: impl FnOnce(&mut ServiceState) -> &mut T,
+        value: T,
+    ) {
+        let Some(service_state) = self.apps.get_mut(app).and_then(|a| a.services.get_mut(service)) else {
+            return;
+        };
+        let slot = field(service_state);
+        if *slot != value {
+            *slot = value;
+            self.changed.send_replace(());
+        }
+    }
+
     pub fn insert_app(&mut self, compose: ComposeFile, compose_path: std::path::PathBuf) {
         let service_order: Vec<String> = compose.services.iter().map(|s| s.name.clone()).collect();
+        // Keep the last known client environment, and the state of services
+        // that are still defined, when an app is re-registered; running
+        // processes must stay tracked
+        let (client_env, mut previous) = match self.apps.remove(&compose.app_name) {
+            Some(app) => (app.client_env, app.services),
+            None => (None, HashMap::new()),
+        };
         let services = compose
             .services
             .into_iter()
             .map(|svc| {
-                let state = ServiceState {
-                    status: ServiceStatus::Stopped,
-                    pid: None,
-                    started_at: None,
-                    exit_code: None,
-                    logs: LogBuffer::new(LOG_BUFFER_CAPACITY),
-                    metrics: ServiceMetrics::default(),
-                    config: svc.clone(),
+                let state = match previous.remove(&svc.name) {
+                    Some(old) => ServiceState {
+                        config: svc.clone(),
+                        ..old
+                    },
+                    None => ServiceState {
+                        status: ServiceStatus::Stopped,
+                        pid: None,
+                        process_start: None,
+                        started_at: None,
+                        exit_code: None,
+                        exit_signal: None,
+                        restart_count: 0,
+                        failure: None,
+                        time_to_ready: None,
+                        run: 0,
+                        stopped_by_hand: false,
+                        output_offsets: [0, 0],
+                        metrics: ServiceMetrics::default(),
+                        config: svc.clone(),
+                    },
                 };
                 (svc.name.clone(), state)
             })
@@ -89,12 +286,28 @@ impl DaemonState {
             profiles: compose.profiles.clone(),
             services,
             service_order,
+            inherit_env: compose.inherit_env,
+            inherit_env_vars: compose.inherit_env_vars.clone(),
+            client_env,
         };
 
         self.apps.insert(compose.app_name, app);
+        self.changed.send_replace(());
+    }
+
+    /// Keeps the part of a client's environment that the app's services inherit.
+    pub fn set_client_env(&mut self, app: &str, env: BTreeMap<String, String>) {
+        if let Some(app_state) = self.apps.get_mut(app) {
+            let vars = env
+                .into_iter()
+                .filter(|(key, _)| app_state.inherits_var(key))
+                .collect();
+            app_state.client_env = Some(vars);
+        }
     }
 
     pub fn remove_app(&mut self, app: &str) -> Option<AppState> {
+        self.changed.send_replace(());
         self.apps.remove(app)
     }
 
@@ -103,78 +316,111 @@ impl DaemonState {
     }
 
     pub fn update_service_status(&mut self, app: &str, service: &str, status: ServiceStatus) {
-        if let Some(app_state) = self.apps.get_mut(app) {
-            if let Some(service_state) = app_state.services.get_mut(service) {
-                service_state.status = status.clone();
-                if !matches!(status, ServiceStatus::Running | ServiceStatus::Starting) {
-                    service_state.started_at = None;
-                }
-            }
+        if !status.is_alive() {
+            self.set_service_start_time(app, service, None);
         }
+        self.set_shown(app, service, |s| &mut s.status, status);
+    }
+
+    /// Moves a service to `Running`, recording how long the first transition
+    /// of the current run took.
+    pub fn mark_ready(&mut self, app: &str, service: &str) {
+        if let Some(service_state) = self.service_mut(app, service)
+            && service_state.time_to_ready.is_none()
+        {
+            let time = service_state
+                .started_at
+                .and_then(|started| started.elapsed().ok());
+            self.set_time_to_ready(app, service, time);
+        }
+        self.set_shown(app, service, |s| &mut s.status, ServiceStatus::Running);
+    }
+
+    pub fn set_time_to_ready(&mut self, app: &str, service: &str, time: Option<Duration>) {
+        self.set_shown(app, service, |s| &mut s.time_to_ready, time);
     }
 
     pub fn set_service_pid(&mut self, app: &str, service: &str, pid: Option<u32>) {
-        if let Some(app_state) = self.apps.get_mut(app) {
-            if let Some(service_state) = app_state.services.get_mut(service) {
-                service_state.pid = pid;
-            }
+        self.set_shown(app, service, |s| &mut s.pid, pid);
+    }
+
+    pub fn set_process_start(&mut self, app: &str, service: &str, start: Option<u64>) {
+        if let Some(service_state) = self.service_mut(app, service) {
+            service_state.process_start = start;
         }
     }
 
     pub fn set_service_start_time(&mut self, app: &str, service: &str, time: Option<SystemTime>) {
-        if let Some(app_state) = self.apps.get_mut(app) {
-            if let Some(service_state) = app_state.services.get_mut(service) {
-                service_state.started_at = time;
-            }
-        }
+        self.set_shown(app, service, |s| &mut s.started_at, time);
     }
 
     pub fn set_exit_code(&mut self, app: &str, service: &str, code: Option<i32>) {
-        if let Some(app_state) = self.apps.get_mut(app) {
-            if let Some(service_state) = app_state.services.get_mut(service) {
-                service_state.exit_code = code;
-            }
+        self.set_shown(app, service, |s| &mut s.exit_code, code);
+    }
+
+    pub fn set_exit_signal(&mut self, app: &str, service: &str, signal: Option<i32>) {
+        self.set_shown(app, service, |s| &mut s.exit_signal, signal);
+    }
+
+    pub fn set_restart_count(&mut self, app: &str, service: &str, count: u32) {
+        self.set_shown(app, service, |s| &mut s.restart_count, count);
+    }
+
+    pub fn set_failure(&mut self, app: &str, service: &str, failure: Option<String>) {
+        self.set_shown(app, service, |s| &mut s.failure, failure);
+    }
+
+    pub fn set_run(&mut self, app: &str, service: &str, run: u32) {
+        if let Some(service_state) = self.service_mut(app, service) {
+            service_state.run = run;
         }
     }
 
-    pub fn push_log(&mut self, app: &str, service: &str, entry: LogEntry) {
-        if let Some(app_state) = self.apps.get_mut(app) {
-            if let Some(service_state) = app_state.services.get_mut(service) {
-                service_state.logs.push(entry);
-            }
+    pub fn set_stopped_by_hand(&mut self, app: &str, service: &str, by_hand: bool) {
+        if let Some(service_state) = self.service_mut(app, service) {
+            service_state.stopped_by_hand = by_hand;
         }
     }
 
-    pub fn clear_service_logs(&mut self, app: &str, service: &str) {
-        if let Some(app_state) = self.apps.get_mut(app) {
-            if let Some(service_state) = app_state.services.get_mut(service) {
-                service_state.logs.clear();
-            }
+    pub fn set_output_offsets(&mut self, app: &str, service: &str, offsets: [u64; 2]) {
+        if let Some(service_state) = self.service_mut(app, service) {
+            service_state.output_offsets = offsets;
         }
     }
 
     pub fn set_metrics(&mut self, app: &str, service: &str, metrics: ServiceMetrics) {
-        if let Some(app_state) = self.apps.get_mut(app) {
-            if let Some(service_state) = app_state.services.get_mut(service) {
-                service_state.metrics = metrics;
-            }
-        }
+        self.set_shown(app, service, |s| &mut s.metrics, metrics);
     }
 
     pub fn set_system_metrics(&mut self, cpu: f32, used: u64, total: u64) {
         self.system_cpu = cpu;
         self.system_memory_used = used;
         self.system_memory_total = total;
+        self.changed.send_replace(());
     }
 
-    pub fn save(&self) -> crate::error::Result<()> {
-        let path = crate::util::app_data_dir()?.join("state.json");
+    /// Writes the state readable by the user only, replacing the old file in
+    /// one step so a crash mid-write cannot leave it truncated.
+    pub fn save(&self) -> Result<()> {
+        let dir = crate::util::app_data_dir()?;
         let content = serde_json::to_string_pretty(self)?;
-        std::fs::write(path, content)?;
+        // Saves may run concurrently under the read lock, so each gets its own temp file
+        static SAVES: AtomicU64 = AtomicU64::new(0);
+        let temp = dir.join(format!("state.json.{}.tmp", SAVES.fetch_add(1, Ordering::Relaxed)));
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temp)?;
+        // `mode` only applies when the file is created
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        file.write_all(content.as_bytes())?;
+        std::fs::rename(temp, dir.join("state.json"))?;
         Ok(())
     }
 
-    pub fn load() -> crate::error::Result<Self> {
+    pub fn load() -> Result<Self> {
         let path = crate::util::app_data_dir()?.join("state.json");
         if !path.exists() {
             return Ok(DaemonState::default());
@@ -197,3 +443,64 @@ pub fn uptime_seconds(started_at: Option<SystemTime>) -> Option<u64> {
         .and_then(|start| start.elapsed().ok())
         .map(|duration| duration.as_secs())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compose(services: &[&str]) -> ComposeFile {
+        let services: Vec<String> = services
+            .iter()
+            .map(|name| format!("  - name: {name}\n    command: sleep 60\n"))
+            .collect();
+        serde_yaml::from_str(&format!("app_name: web\nservices:\n{}", services.concat())).unwrap()
+    }
+
+    #[test]
+    fn registering_again_keeps_running_services() {
+        let mut state = DaemonState::default();
+        state.insert_app(compose(&["api", "worker"]), "compose.yaml".into());
+        state.update_service_status("web", "api", ServiceStatus::Running);
+        state.set_service_pid("web", "api", Some(4242));
+        state.set_process_start("web", "api", Some(17));
+        state.set_run("web", "api", 3);
+        state.set_output_offsets("web", "api", [10, 20]);
+
+        state.insert_app(compose(&["api", "db"]), "compose.yaml".into());
+        let app = state.app("web").unwrap();
+        let api = app.service("api").unwrap();
+        assert!(matches!(api.status, ServiceStatus::Running));
+        assert_eq!(api.pid, Some(4242));
+        assert_eq!(api.process_start, Some(17));
+        assert_eq!(api.run, 3);
+        assert_eq!(api.output_offsets, [10, 20]);
+        assert!(matches!(app.service("db").unwrap().status, ServiceStatus::Stopped));
+        assert!(app.service("worker").is_err());
+    }
+
+    #[test]
+    fn signals_only_changes_shown_in_status() {
+        let mut state = DaemonState::default();
+        state.insert_app(compose(&["api"]), "compose.yaml".into());
+        state.set_service_pid("web", "api", Some(4242));
+        let changed = state.changed();
+
+        state.set_output_offsets("web", "api", [10, 20]);
+        state.set_service_pid("web", "api", Some(4242));
+        assert!(!changed.has_changed().unwrap());
+        state.update_service_status("web", "api", ServiceStatus::Running);
+        assert!(changed.has_changed().unwrap());
+    }
+
+    #[test]
+    fn saves_the_client_environment() {
+        let mut state = DaemonState::default();
+        state.insert_app(compose(&["api"]), "compose.yaml".into());
+        state.set_client_env("web", BTreeMap::from([("PATH".to_string(), "/venv/bin".to_string())]));
+
+        let saved: DaemonState = serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+        let app = saved.app("web").unwrap();
+        let env = app.service_env(&app.service("api").unwrap().config);
+        assert_eq!(env.get("PATH").map(String::as_str), Some("/venv/bin"));
+    }
+}
@@ -1,73 +1,154 @@
-use std::collections::HashMap;
-use std::process::Stdio;
+use std::collections::{HashMap, VecDeque};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
-use tokio::io::{AsyncBufReadExt, BufReader};
+use chrono::Utc;
+use regex::Regex;
+
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::process::Child;
 use tokio::sync::{broadcast, Mutex, RwLock};
 
-use crate::error::{Result, ServinelError};
-use crate::logs::{LogEntry, LogStream};
+use crate::compose::{HealthCheck, ServiceConfig};
+use crate::daemon::health;
+use crate::error::Result;
+use crate::ipc::protocol::Event;
+use crate::logs::{extract_timestamp, LineSplitter, LogEntry, LogFile, LogStore, LogStream, SharedServiceLogs};
 use crate::metrics::ServiceMetrics;
-use crate::daemon::state::{DaemonState, ServiceStatus};
+use crate::daemon::state::{DaemonState, ServiceState, ServiceStatus};
 
 type ServiceKey = (String, String);
 
+const RESTART_BACKOFF_BASE: Duration = Duration::from_secs(1);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(30);
+/// Bytes read from a service's stdout or stderr at a time
+const LOG_READ_CHUNK: usize = 64 << 10;
+/// How often an output file is checked for more once it has been read to the end
+const OUTPUT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Read output is released from disk once at least this many bytes are read
+const OUTPUT_RELEASE_STEP: u64 = 1 << 20;
+
 struct ServiceRuntime {
-    child: Child,
-    log_tx: broadcast::Sender<LogEntry>,
+    process: ServiceProcess,
+    output: OutputHandle,
+}
+
+/// A process we spawned, or one left running by an earlier daemon and
+/// adopted on startup.
+enum ServiceProcess {
+    Child(Child),
+    Adopted { pid: u32, start_time: u64 },
+}
+
+impl ServiceProcess {
+    fn id(&self) -> Option<u32> {
+        match self {
+            ServiceProcess::Child(child) => child.id(),
+            ServiceProcess::Adopted { pid, .. } => Some(*pid),
+        }
+    }
+
+    /// Returns `Some` once the process has exited. Adopted processes are not
+    /// our children, so their exit status is unknown.
+    fn try_wait(&mut self) -> std::io::Result<Option<Option<ExitStatus>>> {
+        match self {
+            ServiceProcess::Child(child) => Ok(child.try_wait()?.map(Some)),
+            ServiceProcess::Adopted { pid, start_time } => {
+                Ok((!process_matches(*pid, *start_time)).then_some(None))
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct RestartTracker {
+    /// Automatic restarts that happened inside the current retry window
+    history: VecDeque<Instant>,
+    /// When the next automatic restart is due, if one is scheduled
+    due: Option<Instant>,
+}
+
+struct HealthTracker {
+    /// Process the probes belong to, so results from a previous run are ignored
+    pid: Option<u32>,
+    next_check: Instant,
+    failures: u32,
+    in_flight: bool,
 }
 
 #[derive(Clone)]
 pub struct Supervisor {
     state: Arc<RwLock<DaemonState>>,
     runtimes: Arc<Mutex<HashMap<ServiceKey, ServiceRuntime>>>,
+    restarts: Arc<Mutex<HashMap<ServiceKey, RestartTracker>>>,
+    health: Arc<Mutex<HashMap<ServiceKey, HealthTracker>>>,
     system: Arc<Mutex<sysinfo::System>>,
+    logs: LogStore,
+    events: broadcast::Sender<Event>,
 }
 
 impl Supervisor {
-    pub fn new(state: Arc<RwLock<DaemonState>>) -> Self {
-        Self {
+    pub fn new(state: Arc<RwLock<DaemonState>>, events: broadcast::Sender<Event>) -> Result<Self> {
+        Ok(Self {
             state,
             runtimes: Arc::new(Mutex::new(HashMap::new())),
+            restarts: Arc::new(Mutex::new(HashMap::new())),
+            health: Arc::new(Mutex::new(HashMap::new())),
             system: Arc::new(Mutex::new(sysinfo::System::new())),
-        }
+            logs: LogStore::new()?,
+            events,
+        })
+    }
+
+    pub fn logs(&self) -> &LogStore {
+        &self.logs
     }
 
     pub async fn start_service(&self, app: &str, service: &str) -> Result<()> {
-        let (command, workdir, pid) = {
+        // A manual start resets the restart policy bookkeeping
+        self.restarts
+            .lock()
+            .await
+            .remove(&(app.to_string(), service.to_string()));
+        self.spawn_service(app, service, 0).await
+    }
+
+    async fn spawn_service(&self, app: &str, service: &str, restart_count: u32) -> Result<()> {
+        let (command, workdir, environment, pid, config, run, marker) = {
             let state = self.state.read().await;
-            let app_state = state
-                .apps
-                .get(app)
-                .ok_or_else(|| ServinelError::AppNotFound(app.to_string()))?;
-            let svc_state = app_state
-                .services
-                .get(service)
-                .ok_or_else(|| ServinelError::ServiceNotFound(service.to_string()))?;
-            let base_dir = app_state
-                .compose_path
-                .parent()
-                .map(|dir| dir.to_path_buf())
-                .unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
-            let workdir = svc_state
-                .config
-                .working_directory
-                .clone()
-                .unwrap_or(base_dir);
-            (svc_state.config.command.clone(), workdir, svc_state.pid)
+            let app_state = state.app(app)?;
+            let svc_state = app_state.service(service)?;
+            let workdir = app_state.service_workdir(&svc_state.config);
+            (
+                svc_state.config.command.clone(),
+                workdir,
+                app_state.service_env(&svc_state.config),
+                recorded_process(svc_state).map(|(pid, _)| pid),
+                svc_state.config.clone(),
+                svc_state.run + 1,
+                restart_marker(svc_state),
+            )
         };
 
+        let key = (app.to_string(), service.to_string());
+        if self.runtimes.lock().await.contains_key(&key) {
+            return Ok(());
+        }
+
         if let Some(p) = pid {
-            // Try to kill any existing process group before starting
-            unsafe {
-                libc::kill(-(p as i32), libc::SIGKILL);
-            }
+            // Stop any leftover process group from an earlier run before
+            // starting, as long as the pid still belongs to that run
+            signal_group(p, config.stop_signal());
+            reap_group(p, Instant::now() + config.stop_grace_period()).await;
         }
 
         let mut runtimes = self.runtimes.lock().await;
-        if runtimes.contains_key(&(app.to_string(), service.to_string())) {
+        if runtimes.contains_key(&key) {
             return Ok(());
         }
 
@@ -76,67 +157,258 @@ impl Supervisor {
         } else {
             format!("cd {} && exec {}", workdir.display(), command)
         };
+        let output = output_paths(app, service)?;
         let mut cmd = tokio::process::Command::new("sh");
         cmd.arg("-c")
             .arg(final_command)
             .current_dir(workdir)
+            .env_clear()
+            .envs(&environment)
             .process_group(0) // Start in a new process group
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+            // Files rather than pipes, so the service can keep writing while
+            // the daemon is down and a new daemon can read on from there
+            .stdout(create_output_file(&output[0])?)
+            .stderr(create_output_file(&output[1])?);
 
-        let mut child = cmd.spawn()?;
+        let child = cmd.spawn()?;
         let pid = child.id();
-        let (log_tx, _) = broadcast::channel(1024);
 
-        if let Some(stdout) = child.stdout.take() {
-            self.spawn_log_task(app, service, LogStream::Stdout, stdout, log_tx.clone());
+        // Services with a readiness check stay in Starting until it passes
+        let status = if config.has_readiness_check() {
+            ServiceStatus::Starting
+        } else {
+            ServiceStatus::Running
+        };
+        // Record the new process before any of its output is read, so a ready
+        // line printed straight away finds it
+        {
+            let mut state = self.state.write().await;
+            state.set_run(app, service, run);
+            state.update_service_status(app, service, status);
+            state.set_service_pid(app, service, pid);
+            state.set_process_start(app, service, pid.and_then(process_start_time));
+            state.set_service_start_time(app, service, Some(SystemTime::now()));
+            state.set_exit_code(app, service, None);
+            state.set_time_to_ready(app, service, None);
+            state.set_restart_count(app, service, restart_count);
+            state.set_stopped_by_hand(app, service, false);
+            state.set_output_offsets(app, service, [0, 0]);
         }
-        if let Some(stderr) = child.stderr.take() {
-            self.spawn_log_task(app, service, LogStream::Stderr, stderr, log_tx.clone());
+        publish(
+            &self.events,
+            Event::Started {
+                app: app.to_string(),
+                service: service.to_string(),
+                pid,
+            },
+        );
+        if config.healthcheck.is_some() {
+            self.health.lock().await.insert(
+                (app.to_string(), service.to_string()),
+                HealthTracker {
+                    pid,
+                    next_check: Instant::now(),
+                    failures: 0,
+                    in_flight: false,
+                },
+            );
+        }
+
+        let logs = self.open_logs(app, service, &config, run).await;
+        if let Some(line) = marker {
+            let marker = LogEntry {
+                timestamp: Utc::now(),
+                stream: LogStream::System,
+                line,
+                run,
+                source_timestamp: None,
+            };
+            logs.lock().unwrap().record(marker.clone());
+            self.logs.write_file(app, service, vec![marker]).await;
         }
+        let ready = ready_watch(&config, pid);
+        let output = self.capture_output(app, service, &config, run, [0, 0], ready);
 
         runtimes.insert(
             (app.to_string(), service.to_string()),
-            ServiceRuntime { child, log_tx },
+            ServiceRuntime {
+                process: ServiceProcess::Child(child),
+                output,
+            },
         );
-
-        let mut state = self.state.write().await;
-        state.clear_service_logs(app, service);
-        state.update_service_status(app, service, ServiceStatus::Running);
-        state.set_service_pid(app, service, pid);
-        state.set_service_start_time(app, service, Some(SystemTime::now()));
-        state.set_exit_code(app, service, None);
         Ok(())
     }
 
-    pub async fn stop_service(&self, app: &str, service: &str) -> Result<()> {
-        let pid = {
+    /// Re-attaches to service processes that outlived a previous daemon. A
+    /// persisted pid is only trusted if it still leads its own process group
+    /// and started at the recorded time; other services that were alive are
+    /// reconciled to `Exited` and handed to their restart policy. Output is
+    /// read on from the files the services write to, starting where the old
+    /// daemon last recorded, so lines printed while no daemon ran are kept.
+    /// Returns the services that were not running but whose restart policy
+    /// brings them back with the daemon.
+    pub async fn adopt_processes(&self) -> Vec<ServiceKey> {
+        let mut exited = Vec::new();
+        let resume;
+        {
+            let mut state = self.state.write().await;
+            resume = state
+                .apps
+                .iter()
+                .flat_map(|(app, app_state)| {
+                    app_state
+                        .services
+                        .iter()
+                        .filter(|(_, svc)| {
+                            // Only services that have run before, and did not fail to start
+                            svc.run > 0
+                                && matches!(svc.status, ServiceStatus::Stopped | ServiceStatus::Exited)
+                                && svc.config.restart_policy().resumes_with_daemon(svc.stopped_by_hand)
+                        })
+                        .map(|(name, _)| (app.clone(), name.clone()))
+                })
+                .collect();
+            let mut runtimes = self.runtimes.lock().await;
+            let mut health = self.health.lock().await;
+            let candidates: Vec<(ServiceKey, ServiceState)> = state
+                .apps
+                .iter()
+                .flat_map(|(app, app_state)| {
+                    app_state
+                        .services
+                        .iter()
+                        .filter(|(_, svc)| svc.status.is_alive() || matches!(svc.status, ServiceStatus::Waiting))
+                        .map(|(name, svc)| ((app.clone(), name.clone()), svc.clone()))
+                })
+                .collect();
+
+            for (key, svc) in candidates {
+                let (app, service) = (&key.0, &key.1);
+                let live = recorded_process(&svc);
+                let config = svc.config;
+                if svc.pid.is_some() {
+                    self.open_logs(app, service, &config, svc.run).await;
+                }
+                if let Some((pid, start_time)) = live {
+                    tracing::info!(app, service, pid, "supervisor: adopted running service");
+                    let ready = matches!(svc.status, ServiceStatus::Starting)
+                        .then(|| ready_watch(&config, Some(pid)))
+                        .flatten();
+                    let output = self.capture_output(app, service, &config, svc.run, svc.output_offsets, ready);
+                    runtimes.insert(
+                        key.clone(),
+                        ServiceRuntime {
+                            process: ServiceProcess::Adopted { pid, start_time },
+                            output,
+                        },
+                    );
+                    if config.healthcheck.is_some() {
+                        health.insert(
+                            key,
+                            HealthTracker {
+                                pid: Some(pid),
+                                next_check: Instant::now(),
+                                failures: 0,
+                                in_flight: false,
+                            },
+                        );
+                    }
+                    continue;
+                }
+
+                // Still waiting on dependencies when the daemon went away
+                if svc.pid.is_none() {
+                    state.update_service_status(app, service, ServiceStatus::Stopped);
+                    continue;
+                }
+                tracing::info!(app, service, "supervisor: service exited while the daemon was down");
+                // Keep whatever it printed before it went
+                self.capture_output(app, service, &config, svc.run, svc.output_offsets, None)
+                    .end();
+                state.update_service_status(app, service, ServiceStatus::Exited);
+                state.set_service_pid(app, service, None);
+                state.set_service_start_time(app, service, None);
+                state.set_exit_code(app, service, None);
+                state.set_exit_signal(app, service, None);
+                publish(&self.events, exited_event(app, service, ServiceStatus::Exited, None, None));
+                exited.push((key, config));
+            }
+            let _ = state.save();
+        }
+
+        for (key, config) in exited {
+            self.schedule_restart(key, &config, None).await;
+        }
+        resume
+    }
+
+    /// Sends the service's stop signal and waits up to its grace period (or
+    /// `timeout`) before escalating to SIGKILL.
+    pub async fn stop_service(
+        &self,
+        app: &str,
+        service: &str,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let key = (app.to_string(), service.to_string());
+        self.restarts.lock().await.remove(&key);
+        self.health.lock().await.remove(&key);
+
+        let (pid, stop_signal, grace) = {
             let state = self.state.read().await;
-            state.apps.get(app)
-                .and_then(|a| a.services.get(service))
-                .and_then(|s| s.pid)
+            let svc_state = state.apps.get(app).and_then(|a| a.services.get(service));
+            (
+                svc_state.and_then(recorded_process).map(|(pid, _)| pid),
+                svc_state.map(|s| s.config.stop_signal()).unwrap_or(libc::SIGTERM),
+                svc_state.map(|s| s.config.stop_grace_period()),
+            )
         };
+        let grace = timeout
+            .or(grace)
+            .unwrap_or(crate::compose::DEFAULT_STOP_GRACE_PERIOD);
+        let deadline = Instant::now() + grace;
 
+        let (process, output) = match self.runtimes.lock().await.remove(&key) {
+            Some(runtime) => (Some(runtime.process), Some(runtime.output)),
+            None => (None, None),
+        };
+        let pid = process.as_ref().and_then(ServiceProcess::id).or(pid);
+
+        // Signal the whole process group so descendants get a chance to shut down too
+        let mut exit_status = None;
         if let Some(p) = pid {
-            // Always try to kill the process group first to ensure all descendants are gone
-            unsafe {
-                libc::kill(-(p as i32), libc::SIGKILL);
+            signal_group(p, stop_signal);
+            if let Some(ServiceProcess::Child(mut child)) = process {
+                exit_status = wait_or_kill(&mut child, p, deadline).await;
             }
+            reap_group(p, deadline).await;
         }
-
-        let mut runtimes = self.runtimes.lock().await;
-        if let Some(mut runtime) = runtimes.remove(&(app.to_string(), service.to_string())) {
-            tokio::spawn(async move {
-                let _ = runtime.child.wait().await;
-            });
+        if let Some(output) = output {
+            output.end();
         }
 
+        let (exit_code, exit_signal) = (exit_status.and_then(|s| s.code()), exit_status.and_then(|s| s.signal()));
+        if pid.is_some() {
+            self.notify_exit(app, service, exit_code, exit_signal);
+        }
         let mut state = self.state.write().await;
+        let was_alive = state
+            .apps
+            .get(app)
+            .and_then(|a| a.services.get(service))
+            .is_some_and(|svc| svc.status.is_alive());
         state.update_service_status(app, service, ServiceStatus::Stopped);
         state.set_service_pid(app, service, None);
         state.set_service_start_time(app, service, None);
-        state.set_exit_code(app, service, None);
+        state.set_exit_code(app, service, exit_code);
+        state.set_exit_signal(app, service, exit_signal);
         state.set_metrics(app, service, crate::metrics::ServiceMetrics::default());
+        if was_alive {
+            publish(
+                &self.events,
+                exited_event(app, service, ServiceStatus::Stopped, exit_code, exit_signal),
+            );
+        }
         Ok(())
     }
 
@@ -159,58 +431,91 @@ impl Supervisor {
             let mut to_remove = Vec::new();
 
             for ((app, service), runtime) in runtimes.iter_mut() {
-                if let Some(status) = runtime.child.try_wait()? {
-                    let exit_code = status.code();
+                updates.push(RefreshUpdate::OutputRead {
+                    app: app.clone(),
+                    service: service.clone(),
+                    offsets: runtime.output.offsets(),
+                });
+                if let Some(status) = runtime.process.try_wait()? {
+                    runtime.output.end();
                     updates.push(RefreshUpdate::Exited {
                         app: app.clone(),
                         service: service.clone(),
-                        exit_code,
+                        exit_code: status.and_then(|status| status.code()),
+                        exit_signal: status.and_then(|status| status.signal()),
                     });
                     to_remove.push((app.clone(), service.clone()));
                     continue;
                 }
 
-                if let Some(pid) = runtime.child.id() {
-                    if let Some(proc) = system.process(sysinfo::Pid::from_u32(pid)) {
-                        let metrics = ServiceMetrics {
-                            cpu: proc.cpu_usage(),
-                            memory: proc.memory(),
-                            memory_total: system_total,
-                        };
-                        updates.push(RefreshUpdate::Metrics {
-                            app: app.clone(),
-                            service: service.clone(),
-                            metrics,
-                        });
-                    }
+                if let Some(pid) = runtime.process.id()
+                    && let Some(proc) = system.process(sysinfo::Pid::from_u32(pid))
+                {
+                    let metrics = ServiceMetrics {
+                        cpu: proc.cpu_usage(),
+                        memory: proc.memory(),
+                        memory_total: system_total,
+                    };
+                    updates.push(RefreshUpdate::Metrics {
+                        app: app.clone(),
+                        service: service.clone(),
+                        metrics,
+                    });
                 }
             }
 
+            let mut health = self.health.lock().await;
             for key in to_remove {
+                health.remove(&key);
                 runtimes.remove(&key);
             }
         }
 
         // Apply updates to state
+        let mut exited = Vec::new();
         let mut state = self.state.write().await;
         state.set_system_metrics(system_metrics.0, system_metrics.1, system_metrics.2);
         for update in &updates {
             match update {
-                RefreshUpdate::Exited { app, service, exit_code } => {
-                    state.update_service_status(&app, &service, ServiceStatus::Exited);
-                    state.set_service_pid(&app, &service, None);
-                    state.set_service_start_time(&app, &service, None);
-                    state.set_exit_code(&app, &service, *exit_code);
-                    state.set_metrics(&app, &service, ServiceMetrics::default());
+                RefreshUpdate::Exited {
+                    app,
+                    service,
+                    exit_code,
+                    exit_signal,
+                } => {
+                    self.notify_exit(app, service, *exit_code, *exit_signal);
+                    state.update_service_status(app, service, ServiceStatus::Exited);
+                    state.set_service_pid(app, service, None);
+                    state.set_service_start_time(app, service, None);
+                    state.set_exit_code(app, service, *exit_code);
+                    state.set_exit_signal(app, service, *exit_signal);
+                    state.set_metrics(app, service, ServiceMetrics::default());
+                    publish(
+                        &self.events,
+                        exited_event(app, service, ServiceStatus::Exited, *exit_code, *exit_signal),
+                    );
+                    if let Some(svc_state) = state
+                        .apps
+                        .get(app.as_str())
+                        .and_then(|a| a.services.get(service.as_str()))
+                    {
+                        exited.push((
+                            (app.clone(), service.clone()),
+                            svc_state.config.clone(),
+                            *exit_code,
+                        ));
+                    }
+                }
+                RefreshUpdate::OutputRead { app, service, offsets } => {
+                    state.set_output_offsets(app, service, *offsets);
                 }
                 RefreshUpdate::Metrics { app, service, metrics } => {
                      // Check if service is still running to prevent overwriting 'Stopped' state with stale metrics
-                    if let Some(app_state) = state.apps.get(app.as_str()) {
-                        if let Some(svc_state) = app_state.services.get(service.as_str()) {
-                            if matches!(svc_state.status, ServiceStatus::Running | ServiceStatus::Starting) {
-                                state.set_metrics(&app, &service, metrics.clone());
-                            }
-                        }
+                    if let Some(app_state) = state.apps.get(app.as_str())
+                        && let Some(svc_state) = app_state.services.get(service.as_str())
+                        && svc_state.status.is_alive()
+                    {
+                        state.set_metrics(app, service, metrics.clone());
                     }
                 }
             }
@@ -219,42 +524,451 @@ impl Supervisor {
         if !updates.is_empty() {
             let _ = state.save();
         }
+        drop(state);
+
+        for (key, config, exit_code) in exited {
+            self.schedule_restart(key, &config, exit_code).await;
+        }
+        self.run_due_restarts().await;
 
         Ok(())
     }
 
-    pub async fn log_sender(&self, app: &str, service: &str) -> Option<broadcast::Sender<LogEntry>> {
-        let runtimes = self.runtimes.lock().await;
-        runtimes
-            .get(&(app.to_string(), service.to_string()))
-            .map(|runtime| runtime.log_tx.clone())
+    /// Applies the service's restart policy after it exited on its own.
+    async fn schedule_restart(&self, key: ServiceKey, config: &ServiceConfig, exit_code: Option<i32>) {
+        if !config.restart_policy().should_restart(exit_code) {
+            return;
+        }
+        let now = Instant::now();
+        let window = config.restart_window();
+        let mut restarts = self.restarts.lock().await;
+        let tracker = restarts.entry(key.clone()).or_default();
+        while tracker
+            .history
+            .front()
+            .is_some_and(|at| now.duration_since(*at) > window)
+        {
+            tracker.history.pop_front();
+        }
+        if tracker.history.len() as u32 >= config.max_restarts() {
+            tracing::warn!(app = key.0, service = key.1, "supervisor: restart limit reached, giving up");
+            return;
+        }
+        let attempt = tracker.history.len().min(16) as u32;
+        let delay = RESTART_BACKOFF_BASE
+            .saturating_mul(1 << attempt)
+            .min(RESTART_BACKOFF_MAX);
+        tracing::info!(app = key.0, service = key.1, ?delay, "supervisor: scheduling restart");
+        tracker.due = Some(now + delay);
+    }
+
+    async fn run_due_restarts(&self) {
+        let now = Instant::now();
+        let due: Vec<ServiceKey> = {
+            let mut restarts = self.restarts.lock().await;
+            restarts
+                .iter_mut()
+                .filter(|(_, tracker)| tracker.due.is_some_and(|at| at <= now))
+                .map(|(key, tracker)| {
+                    tracker.due = None;
+                    tracker.history.push_back(now);
+                    key.clone()
+                })
+                .collect()
+        };
+
+        for (app, service) in due {
+            let restart_count = {
+                let state = self.state.read().await;
+                state
+                    .apps
+                    .get(&app)
+                    .and_then(|a| a.services.get(&service))
+                    .map(|svc| svc.restart_count + 1)
+            };
+            let Some(restart_count) = restart_count else {
+                continue;
+            };
+            if let Err(err) = self.spawn_service(&app, &service, restart_count).await {
+                tracing::error!(?err, app, service, "supervisor: automatic restart failed");
+            }
+        }
+    }
+
+    /// Launches the health probes that are due. Probes run in the background so
+    /// a slow check never delays the tick loop.
+    pub async fn check_health(&self) {
+        let now = Instant::now();
+        let due: Vec<(ServiceKey, Option<u32>)> = {
+            let mut health = self.health.lock().await;
+            health
+                .iter_mut()
+                .filter(|(_, tracker)| !tracker.in_flight && tracker.next_check <= now)
+                .map(|(key, tracker)| {
+                    tracker.in_flight = true;
+                    (key.clone(), tracker.pid)
+                })
+                .collect()
+        };
+
+        for (key, pid) in due {
+            let probe_target = {
+                let state = self.state.read().await;
+                state.apps.get(&key.0).and_then(|app_state| {
+                    let svc_state = app_state.services.get(&key.1)?;
+                    let check = svc_state.config.healthcheck.clone()?;
+                    Some((
+                        check,
+                        app_state.service_workdir(&svc_state.config),
+                        app_state.service_env(&svc_state.config),
+                    ))
+                })
+            };
+            let Some((check, workdir, environment)) = probe_target else {
+                self.health.lock().await.remove(&key);
+                continue;
+            };
+            let supervisor = self.clone();
+            tokio::spawn(async move {
+                let probe = health::probe(&check, &workdir, &environment);
+                let result = tokio::time::timeout(check.timeout(), probe)
+                    .await
+                    .unwrap_or_else(|_| Err("timed out".to_string()));
+                supervisor.apply_health(key, pid, &check, result).await;
+            });
+        }
+    }
+
+    async fn apply_health(
+        &self,
+        key: ServiceKey,
+        pid: Option<u32>,
+        check: &HealthCheck,
+        result: std::result::Result<(), String>,
+    ) {
+        let failures = {
+            let mut health = self.health.lock().await;
+            let Some(tracker) = health.get_mut(&key) else {
+                return;
+            };
+            if tracker.pid != pid {
+                return;
+            }
+            tracker.in_flight = false;
+            tracker.next_check = Instant::now() + check.interval();
+            match &result {
+                Ok(()) => tracker.failures = 0,
+                Err(_) => tracker.failures += 1,
+            }
+            tracker.failures
+        };
+
+        let (app, service) = (&key.0, &key.1);
+        let mut state = self.state.write().await;
+        let Some(current) = state
+            .apps
+            .get(app)
+            .and_then(|a| a.services.get(service))
+            .map(|svc| svc.status.clone())
+        else {
+            return;
+        };
+        if result.is_ok() && matches!(current, ServiceStatus::Starting | ServiceStatus::Unhealthy) {
+            tracing::info!(app, service, "supervisor: health check passed");
+            change_health(&mut state, &self.events, app, service, ServiceStatus::Running);
+            let _ = state.save();
+            return;
+        }
+        let next = match (&result, current) {
+            (Err(_), ServiceStatus::Starting | ServiceStatus::Running)
+                if failures >= check.retries() =>
+            {
+                ServiceStatus::Unhealthy
+            }
+            _ => return,
+        };
+        if let Err(reason) = &result {
+            tracing::warn!(app, service, reason, "supervisor: health check failed");
+        }
+        tracing::info!(app, service, status = next.as_str(), "supervisor: health changed");
+        change_health(&mut state, &self.events, app, service, next);
+        let _ = state.save();
+    }
+
+    /// Tells followers of the service's logs that its process has ended.
+    fn notify_exit(&self, app: &str, service: &str, exit_code: Option<i32>, exit_signal: Option<i32>) {
+        let logs = self.logs.service(app, service);
+        let logs = logs.lock().unwrap();
+        logs.notify(LogEntry {
+            timestamp: Utc::now(),
+            stream: LogStream::System,
+            line: format!("--- exited{} ---", exit_reason(exit_code, exit_signal)),
+            run: logs.buffer.current_run().unwrap_or_default(),
+            source_timestamp: None,
+        });
+    }
+
+    /// Opens the logs of `run` of a service, with its log file when
+    /// `logging.dir` is set. Called before any of the run's output is read.
+    async fn open_logs(&self, app: &str, service: &str, config: &ServiceConfig, run: u32) -> SharedServiceLogs {
+        let logging = config.logging.clone().unwrap_or_default();
+        let logs = self.logs.service(app, service);
+        logs.lock().unwrap().buffer.start_run(run);
+        let file = config
+            .log_file()
+            .map(|path| LogFile::new(path, logging.max_size(), logging.max_files()));
+        self.logs.set_file(app, service, file).await;
+        logs
     }
 
+    /// Starts tailing a run's stdout and stderr files from `offsets` into the
+    /// service's logs.
+    fn capture_output(
+        &self,
+        app: &str,
+        service: &str,
+        config: &ServiceConfig,
+        run: u32,
+        offsets: [u64; 2],
+        ready: Option<Arc<ReadyWatch>>,
+    ) -> OutputHandle {
+        let output = OutputHandle {
+            ended: Arc::new(AtomicBool::new(false)),
+            offsets: offsets.map(|offset| Arc::new(AtomicU64::new(offset))),
+        };
+        let paths = match output_paths(app, service) {
+            Ok(paths) => paths,
+            Err(err) => {
+                tracing::error!(?err, app, service, "supervisor: cannot locate service output");
+                return output;
+            }
+        };
+        let logging = config.logging.clone().unwrap_or_default();
+        let capture = LogCapture {
+            ready: ready.clone(),
+            logs: self.logs.service(app, service),
+            store: self.logs.clone(),
+            to_file: config.log_file().is_some(),
+            bytes_dropped: self.logs.bytes_dropped_counter(app, service),
+            run,
+            max_line_length: logging.max_line_length(),
+            timestamp_format: config
+                .timestamp_format
+                .as_deref()
+                .and_then(|pattern| Regex::new(pattern).ok()),
+        };
+        let streams = [LogStream::Stdout, LogStream::Stderr];
+        for ((stream, path), offset) in streams.into_iter().zip(paths).zip(&output.offsets) {
+            let tail = OutputTail {
+                path,
+                offset: offset.clone(),
+                ended: output.ended.clone(),
+            };
+            self.spawn_log_task(app, service, stream, tail, capture.clone());
+        }
+        if let Some(ready) = ready {
+            self.spawn_ready_timeout(app, service, ready, config.ready_timeout());
+        }
+        output
+    }
+
+    /// Follows one output file of a run until the process has ended and the
+    /// file is read to the end, then removes it.
     fn spawn_log_task(
         &self,
         app: &str,
         service: &str,
         stream: LogStream,
-        reader: impl tokio::io::AsyncRead + Unpin + Send + 'static,
-        log_tx: broadcast::Sender<LogEntry>,
+        tail: OutputTail,
+        capture: LogCapture,
     ) {
+        let LogCapture {
+            ready,
+            logs,
+            store,
+            to_file,
+            bytes_dropped,
+            run,
+            max_line_length,
+            timestamp_format,
+        } = capture;
         let app = app.to_string();
         let service = service.to_string();
         let state = self.state.clone();
+        let events = self.events.clone();
         tokio::spawn(async move {
-            let mut reader = BufReader::new(reader).lines();
-            while let Ok(Some(line)) = reader.next_line().await {
-                let entry = LogEntry {
-                    timestamp: current_timestamp(),
-                    stream,
-                    line,
-                    };
-                let mut state = state.write().await;
-                state.push_log(&app, &service, entry.clone());
-                let _ = log_tx.send(entry);
+            let mut file = match open_output(&tail.path, tail.offset.load(Ordering::Relaxed)).await {
+                Ok(file) => file,
+                Err(err) => {
+                    tracing::warn!(?err, path = ?tail.path, "supervisor: cannot read service output");
+                    return;
                 }
-            });
-        }
+            };
+            let mut splitter = LineSplitter::new(max_line_length);
+            let mut buf = vec![0; LOG_READ_CHUNK];
+            let mut reported = 0;
+            #[cfg(target_os = "linux")]
+            let mut released = 0;
+            let mut open = true;
+            while open {
+                // Checked before reading, so output written just before the
+                // process ended is still read
+                let ended = tail.ended.load(Ordering::Acquire);
+                let lines = match file.read(&mut buf).await {
+                    Ok(read) if read > 0 => {
+                        tail.offset.fetch_add(read as u64, Ordering::Relaxed);
+                        #[cfg(target_os = "linux")]
+                        {
+                            let offset = tail.offset.load(Ordering::Relaxed);
+                            if offset - released >= OUTPUT_RELEASE_STEP {
+                                release_consumed(&file, offset);
+                                released = offset;
+                            }
+                        }
+                        splitter.push(&buf[..read])
+                    }
+                    Ok(_) if !ended => {
+                        #[cfg(not(target_os = "linux"))]
+                        if tail.offset.load(Ordering::Relaxed) >= OUTPUT_RELEASE_STEP {
+                            truncate_consumed(&mut file, &tail.offset).await;
+                        }
+                        tokio::time::sleep(OUTPUT_POLL_INTERVAL).await;
+                        continue;
+                    }
+                    _ => {
+                        open = false;
+                        splitter.finish().into_iter().collect()
+                    }
+                };
+                let dropped = splitter.dropped() - reported;
+                reported = splitter.dropped();
+
+                let timestamp = Utc::now();
+                let mut became_ready = false;
+                let entries: Vec<LogEntry> = lines
+                    .into_iter()
+                    .map(|line| {
+                        became_ready |= ready.as_ref().is_some_and(|ready| {
+                            !ready.matched.load(Ordering::Relaxed)
+                                && ready.pattern.is_match(&line)
+                                && !ready.matched.swap(true, Ordering::Relaxed)
+                        });
+                        let source_timestamp = timestamp_format
+                            .as_ref()
+                            .and_then(|format| extract_timestamp(format, &line));
+                        LogEntry {
+                            timestamp,
+                            stream,
+                            line,
+                            run,
+                            source_timestamp,
+                        }
+                    })
+                    .collect();
+                bytes_dropped.fetch_add(dropped, Ordering::Relaxed);
+                let to_write = if to_file { entries.clone() } else { Vec::new() };
+                {
+                    let mut logs = logs.lock().unwrap();
+                    for entry in entries {
+                        logs.record(entry);
+                    }
+                }
+                store.write_file(&app, &service, to_write).await;
+                // Only the first matching line needs the daemon state
+                if became_ready && let Some(ready) = &ready {
+                    let mut state = state.write().await;
+                    let waiting = state
+                        .apps
+                        .get(&app)
+                        .and_then(|a| a.services.get(&service))
+                        .is_some_and(|svc| {
+                            svc.pid == ready.pid
+                                && matches!(svc.status, ServiceStatus::Starting | ServiceStatus::Unhealthy)
+                        });
+                    if waiting {
+                        tracing::info!(app, service, "supervisor: ready log line seen");
+                        change_health(&mut state, &events, &app, &service, ServiceStatus::Running);
+                        let _ = state.save();
+                    }
+                }
+            }
+            remove_output(&tail.path, &file).await;
+        });
+    }
+
+    /// Marks the service unhealthy if its ready log line has not appeared
+    /// within `timeout`. A later match still makes it ready.
+    fn spawn_ready_timeout(&self, app: &str, service: &str, ready: Arc<ReadyWatch>, timeout: Duration) {
+        let app = app.to_string();
+        let service = service.to_string();
+        let state = self.state.clone();
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            if ready.matched.load(Ordering::Relaxed) {
+                return;
+            }
+            let mut state = state.write().await;
+            let starting = state
+                .apps
+                .get(&app)
+                .and_then(|a| a.services.get(&service))
+                .is_some_and(|svc| svc.pid == ready.pid && matches!(svc.status, ServiceStatus::Starting));
+            if starting {
+                tracing::warn!(app, service, ?timeout, "supervisor: ready log line not seen in time");
+                change_health(&mut state, &events, &app, &service, ServiceStatus::Unhealthy);
+                let _ = state.save();
+            }
+        });
+    }
+}
+
+/// Where the output of one run of a service goes, shared by its stdout and
+/// stderr readers
+#[derive(Clone)]
+struct LogCapture {
+    ready: Option<Arc<ReadyWatch>>,
+    logs: SharedServiceLogs,
+    store: LogStore,
+    /// Whether the service has a log file to write to
+    to_file: bool,
+    bytes_dropped: Arc<AtomicU64>,
+    run: u32,
+    max_line_length: usize,
+    timestamp_format: Option<Regex>,
+}
+
+/// Shared with the tasks tailing a run's output files
+struct OutputHandle {
+    /// Set once the process has exited; the tails then read what is left and stop
+    ended: Arc<AtomicBool>,
+    /// Bytes of stdout and stderr read so far
+    offsets: [Arc<AtomicU64>; 2],
+}
+
+impl OutputHandle {
+    fn end(&self) {
+        self.ended.store(true, Ordering::Release);
+    }
+
+    fn offsets(&self) -> [u64; 2] {
+        [0, 1].map(|index| self.offsets[index].load(Ordering::Relaxed))
+    }
+}
+
+/// One output file of a run and how far it has been read
+struct OutputTail {
+    path: PathBuf,
+    offset: Arc<AtomicU64>,
+    ended: Arc<AtomicBool>,
+}
+
+/// Readiness pattern for one run of a service
+struct ReadyWatch {
+    pattern: Regex,
+    /// Process the watch belongs to, so a later run is not affected
+    pid: Option<u32>,
+    matched: AtomicBool,
 }
 
 enum RefreshUpdate {
@@ -262,17 +976,228 @@ enum RefreshUpdate {
         app: String,
         service: String,
         exit_code: Option<i32>,
+        exit_signal: Option<i32>,
     },
     Metrics {
         app: String,
         service: String,
         metrics: ServiceMetrics,
     },
+    /// How far the service's output files have been read
+    OutputRead {
+        app: String,
+        service: String,
+        offsets: [u64; 2],
+    },
 }
 
-fn current_timestamp() -> u64 {
-    SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .map(|d| d.as_secs())
-        .unwrap_or_default()
+/// Sends `event` to subscribers; fails only while nobody is subscribed.
+fn publish(events: &broadcast::Sender<Event>, event: Event) {
+    let _ = events.send(event);
+}
+
+/// Moves a live service to `status`, `Running` counting as ready, and tells
+/// subscribers.
+fn change_health(
+    state: &mut DaemonState,
+    events: &broadcast::Sender<Event>,
+    app: &str,
+    service: &str,
+    status: ServiceStatus,
+) {
+    publish(
+        events,
+        Event::HealthChanged {
+            app: app.to_string(),
+            service: service.to_string(),
+            status: status.as_str().to_string(),
+        },
+    );
+    match status {
+        ServiceStatus::Running => state.mark_ready(app, service),
+        status => state.update_service_status(app, service, status),
+    }
+}
+
+pub(crate) fn exited_event(
+    app: &str,
+    service: &str,
+    status: ServiceStatus,
+    exit_code: Option<i32>,
+    exit_signal: Option<i32>,
+) -> Event {
+    Event::Exited {
+        app: app.to_string(),
+        service: service.to_string(),
+        status: status.as_str().to_string(),
+        exit_code,
+        exit_signal,
+    }
+}
+
+/// Both streams share one watch so only the first matching line counts
+fn ready_watch(config: &ServiceConfig, pid: Option<u32>) -> Option<Arc<ReadyWatch>> {
+    let pattern = Regex::new(config.ready_when_log.as_deref()?).ok()?;
+    Some(Arc::new(ReadyWatch {
+        pattern,
+        pid,
+        matched: AtomicBool::new(false),
+    }))
+}
+
+/// Files a service's stdout and stderr go to
+fn output_paths(app: &str, service: &str) -> Result<[PathBuf; 2]> {
+    let dir = crate::util::output_dir()?.join(app);
+    Ok([
+        dir.join(format!("{service}.stdout")),
+        dir.join(format!("{service}.stderr")),
+    ])
+}
+
+/// Creates the output file of a new run. The previous one is unlinked rather
+/// than truncated, so a tail still draining the last run is unaffected.
+fn create_output_file(path: &Path) -> std::io::Result<std::fs::File> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    std::fs::OpenOptions::new()
+        .append(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+async fn open_output(path: &Path, offset: u64) -> std::io::Result<tokio::fs::File> {
+    // Writable too, so read output can be released
+    let mut file = tokio::fs::OpenOptions::new().read(true).write(true).open(path).await?;
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    Ok(file)
+}
+
+/// Frees the disk space of output that has been read, up to `offset`. The
+/// file keeps its size so offsets stay valid; on filesystems that cannot punch
+/// holes the data simply stays until the run ends.
+#[cfg(target_os = "linux")]
+fn release_consumed(file: &tokio::fs::File, offset: u64) {
+    use std::os::fd::AsRawFd;
+
+    let len = offset - offset % OUTPUT_RELEASE_STEP;
+    unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            0,
+            len as libc::off_t,
+        );
+    }
+}
+
+/// Where holes cannot be punched, empties an output file once it has been
+/// read to the end. The service appends, so its next write lands at the
+/// start again. Output written between the size check and the truncation is
+/// lost, so this only runs once a whole step has been read.
+#[cfg(not(target_os = "linux"))]
+async fn truncate_consumed(file: &mut tokio::fs::File, offset: &AtomicU64) {
+    let read = offset.load(Ordering::Relaxed);
+    if !file.metadata().await.is_ok_and(|metadata| metadata.len() == read) {
+        return;
+    }
+    if file.set_len(0).await.is_ok() && file.seek(std::io::SeekFrom::Start(0)).await.is_ok() {
+        offset.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Removes a finished run's output file, unless a new run has replaced it.
+async fn remove_output(path: &Path, file: &tokio::fs::File) {
+    let (Ok(ours), Ok(current)) = (file.metadata().await, tokio::fs::metadata(path).await) else {
+        return;
+    };
+    if (ours.dev(), ours.ino()) == (current.dev(), current.ino()) {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+}
+
+fn signal_group(pgid: u32, signal: i32) {
+    unsafe {
+        libc::kill(-(pgid as i32), signal);
+    }
+}
+
+/// Reads a live process's start time (clock ticks after boot) and process
+/// group from `/proc/<pid>/stat`. Zombies count as gone.
+fn proc_stat(pid: u32) -> Option<(u64, u32)> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The command name may contain spaces, so split after its closing paren
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    if fields.first() == Some(&"Z") {
+        return None;
+    }
+    let pgid = fields.get(2)?.parse().ok()?;
+    let start_time = fields.get(19)?.parse().ok()?;
+    Some((start_time, pgid))
+}
+
+fn process_start_time(pid: u32) -> Option<u64> {
+    proc_stat(pid).map(|(start_time, _)| start_time)
+}
+
+/// Pid and start time recorded for a service, if that process is still the
+/// one running under the pid.
+fn recorded_process(svc: &ServiceState) -> Option<(u32, u64)> {
+    svc.pid
+        .zip(svc.process_start)
+        .filter(|(pid, start)| process_matches(*pid, *start))
+}
+
+/// Whether `pid` is still the group leader we started, not a reused pid.
+fn process_matches(pid: u32, start_time: u64) -> bool {
+    proc_stat(pid) == Some((start_time, pid))
+}
+
+fn group_alive(pgid: u32) -> bool {
+    unsafe { libc::kill(-(pgid as i32), 0) == 0 }
+}
+
+/// Waits for the child until `deadline`, then SIGKILLs its process group.
+async fn wait_or_kill(child: &mut Child, pgid: u32, deadline: Instant) -> Option<ExitStatus> {
+    let deadline = tokio::time::Instant::from_std(deadline);
+    match tokio::time::timeout_at(deadline, child.wait()).await {
+        Ok(status) => status.ok(),
+        Err(_) => {
+            signal_group(pgid, libc::SIGKILL);
+            child.wait().await.ok()
+        }
+    }
+}
+
+/// Waits for every remaining process in the group to exit, escalating to
+/// SIGKILL once `deadline` passes.
+async fn reap_group(pgid: u32, deadline: Instant) {
+    while group_alive(pgid) {
+        if Instant::now() >= deadline {
+            signal_group(pgid, libc::SIGKILL);
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Line that separates a restarted service's output from its previous run
+fn restart_marker(svc: &ServiceState) -> Option<String> {
+    if svc.run == 0 {
+        return None;
+    }
+    Some(format!("--- restarted{} ---", exit_reason(svc.exit_code, svc.exit_signal)))
+}
+
+fn exit_reason(exit_code: Option<i32>, exit_signal: Option<i32>) -> String {
+    match (exit_code, exit_signal) {
+        (Some(code), _) => format!(" (exit code {code})"),
+        (None, Some(signal)) => format!(" ({})", crate::util::signal_name(signal)),
+        (None, None) => String::new(),
+    }
 }
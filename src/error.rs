@@ -1,7 +1,10 @@
 use std::path::PathBuf;
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::ipc::protocol::ErrorResponse;
+
 pub type Result<T> = std::result::Result<T, ServinelError>;
 
 #[derive(Error, Debug)]
@@ -16,14 +19,100 @@ pub enum ServinelError {
     ComposeNotFound(PathBuf),
     #[error("Invalid compose file: {0}")]
     InvalidCompose(String),
-    #[error("App not found: {0}")]
-    AppNotFound(String),
-    #[error("Service not found: {0}")]
-    ServiceNotFound(String),
-    #[error("Profile not found: {0}")]
-    ProfileNotFound(String),
+    /// `candidates` are the names that do exist, for suggestions
+    #[error("App not found: {name}")]
+    AppNotFound { name: String, candidates: Vec<String> },
+    #[error("Service not found: {name}")]
+    ServiceNotFound { name: String, candidates: Vec<String> },
+    #[error("Profile not found: {name}")]
+    ProfileNotFound { name: String, candidates: Vec<String> },
+    #[error("Services failed to start: {0}")]
+    StartFailed(String),
     #[error("Daemon is not running")]
     DaemonNotRunning,
+    #[error("Daemon is already running (pid {0})")]
+    DaemonAlreadyRunning(u32),
     #[error("CLI usage error: {0}")]
     Usage(String),
+    /// An error the daemon reported in answer to a request
+    #[error("{}", .0.message)]
+    Remote(ErrorResponse),
+}
+
+impl ServinelError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ServinelError::Io(_) | ServinelError::Yaml(_) | ServinelError::Json(_) => ErrorKind::Internal,
+            ServinelError::ComposeNotFound(_) => ErrorKind::ComposeNotFound,
+            ServinelError::InvalidCompose(_) => ErrorKind::InvalidCompose,
+            ServinelError::AppNotFound { .. } => ErrorKind::AppNotFound,
+            ServinelError::ServiceNotFound { .. } => ErrorKind::ServiceNotFound,
+            ServinelError::ProfileNotFound { .. } => ErrorKind::ProfileNotFound,
+            ServinelError::StartFailed(_) => ErrorKind::SpawnFailed,
+            ServinelError::DaemonNotRunning => ErrorKind::DaemonNotRunning,
+            ServinelError::DaemonAlreadyRunning(_) => ErrorKind::DaemonAlreadyRunning,
+            ServinelError::Usage(_) => ErrorKind::Usage,
+            ServinelError::Remote(error) => error.kind,
+        }
+    }
+}
+
+impl From<ErrorResponse> for ServinelError {
+    fn from(error: ErrorResponse) -> Self {
+        ServinelError::Remote(error)
+    }
+}
+
+impl From<&ServinelError> for ErrorResponse {
+    fn from(err: &ServinelError) -> Self {
+        let (name, candidates) = match err {
+            ServinelError::Remote(error) => return error.clone(),
+            ServinelError::AppNotFound { name, candidates }
+            | ServinelError::ServiceNotFound { name, candidates }
+            | ServinelError::ProfileNotFound { name, candidates } => (Some(name.clone()), candidates.clone()),
+            _ => (None, Vec::new()),
+        };
+        ErrorResponse {
+            kind: err.kind(),
+            message: err.to_string(),
+            name,
+            candidates,
+        }
+    }
+}
+
+/// Class of an error, sent to clients and used as the CLI's exit status so
+/// scripts can tell failures apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Internal,
+    Usage,
+    DaemonNotRunning,
+    DaemonAlreadyRunning,
+    ComposeNotFound,
+    InvalidCompose,
+    AppNotFound,
+    ServiceNotFound,
+    ProfileNotFound,
+    SpawnFailed,
+}
+
+impl ErrorKind {
+    /// Process exit status for errors of this kind. 2 matches the status
+    /// for invalid command line arguments.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Internal => 1,
+            ErrorKind::Usage => 2,
+            ErrorKind::DaemonNotRunning => 3,
+            ErrorKind::DaemonAlreadyRunning => 4,
+            ErrorKind::ComposeNotFound => 5,
+            ErrorKind::InvalidCompose => 6,
+            ErrorKind::AppNotFound => 7,
+            ErrorKind::ServiceNotFound => 8,
+            ErrorKind::ProfileNotFound => 9,
+            ErrorKind::SpawnFailed => 10,
+        }
+    }
 }
@@ -1,10 +1,20 @@
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::UnixStream;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TryRecvError;
 
+use crate::daemon::pidfile::running_pid;
 use crate::error::{Result, ServinelError};
-use crate::ipc::protocol::{LogChunk, Request, Response};
+use crate::ipc::protocol::{
+    ClientFrame, DaemonInfo, LogChunk, Request, Response, ServerFrame, ServiceSelector, PROTOCOL_VERSION,
+};
 use crate::util::{ensure_app_dir, socket_path};
 
 const DAEMON_RETRY_ATTEMPTS: usize = 15;
@@ -12,16 +22,19 @@ const DAEMON_RETRY_DELAY_MS: u64 = 300;
 
 pub async fn ensure_daemon() -> Result<()> {
     ensure_app_dir()?;
-    if ping_daemon().await.is_ok() {
-        return Ok(());
+    match hello().await {
+        Ok(info) => check_daemon_version(info.as_ref()).await,
+        Err(_) => start_daemon().await,
     }
+}
 
+async fn start_daemon() -> Result<()> {
     cleanup_socket_if_stale()?;
-    if !daemon_process_running() {
+    if running_pid().is_none() {
         spawn_daemon()?;
     }
     for _ in 0..DAEMON_RETRY_ATTEMPTS {
-        if ping_daemon().await.is_ok() {
+        if hello().await.is_ok() {
             return Ok(());
         }
         tokio::time::sleep(Duration::from_millis(DAEMON_RETRY_DELAY_MS)).await;
@@ -35,39 +48,110 @@ pub async fn connect() -> Result<UnixStream> {
     Ok(UnixStream::connect(path).await?)
 }
 
-async fn ping_daemon() -> Result<()> {
-    let request = Request::DashAttach;
+/// Asks the daemon which version it is. `Ok(None)` means a daemon answered
+/// but did not understand the request, so it predates the handshake.
+pub async fn hello() -> Result<Option<DaemonInfo>> {
+    let request = Request::Hello {
+        client_version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol_version: PROTOCOL_VERSION,
+    };
     let response = tokio::time::timeout(Duration::from_secs(1), request_response(&request))
         .await
-        .map_err(|_| ServinelError::DaemonNotRunning)?
-        ?;
+        .map_err(|_| ServinelError::DaemonNotRunning)?;
     match response {
-        Response::Ack | Response::StatusSnapshot(_) | Response::ProfilesList(_) => Ok(()),
-        Response::Error(message) => Err(ServinelError::Usage(message)),
-        _ => Ok(()),
+        Ok(Response::Hello(info)) => Ok(Some(info)),
+        // Older daemons hang up on requests they cannot parse
+        Ok(_) | Err(ServinelError::DaemonNotRunning | ServinelError::Json(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Offers to restart a daemon from another servinel version, which may not
+/// understand this CLI. Scripts only get a warning.
+async fn check_daemon_version(info: Option<&DaemonInfo>) -> Result<()> {
+    let version = env!("CARGO_PKG_VERSION");
+    let daemon_version = match info {
+        Some(info) if info.version == version && info.protocol_version == PROTOCOL_VERSION => return Ok(()),
+        Some(info) => format!("servinel {}", info.version),
+        None => "an older servinel".to_string(),
+    };
+    eprintln!("The running daemon is {daemon_version}, but this is servinel {version}.");
+    if !(std::io::stdin().is_terminal() && std::io::stderr().is_terminal()) {
+        eprintln!("Run `servinel daemon restart` to upgrade it.");
+        return Ok(());
+    }
+    eprint!("Restart it now? Running services are restarted with it. [y/N] ");
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if answer.trim().eq_ignore_ascii_case("y") {
+        restart_daemon().await?;
+    }
+    Ok(())
+}
+
+/// Replaces the running daemon with one from this version of servinel, then
+/// starts again whatever was running under the old one.
+pub async fn restart_daemon() -> Result<()> {
+    let mut running = Vec::new();
+    // An old daemon's snapshot may not parse; its services then stay stopped
+    if let Ok(Response::StatusSnapshot(snapshot)) = request_response(&Request::Status {
+        app: None,
+        selector: ServiceSelector::All,
+    })
+    .await
+    {
+        for app in snapshot.apps {
+            let services: Vec<String> = app
+                .services
+                .iter()
+                .filter(|service| service.is_alive())
+                .map(|service| service.name.clone())
+                .collect();
+            if !services.is_empty() {
+                running.push((app.app_name, services));
+            }
+        }
+    }
+
+    if running_pid().is_some() {
+        shutdown_daemon().await?;
+    }
+    start_daemon().await?;
+    for (app, services) in running {
+        let request = Request::Start {
+            file: None,
+            overrides: Vec::new(),
+            app: Some(app),
+            selector: ServiceSelector::Services(services),
+            client: None,
+        };
+        if let Response::Error(error) = request_response(&request).await? {
+            return Err(error.into());
+        }
     }
+    Ok(())
 }
 
 fn cleanup_socket_if_stale() -> Result<()> {
     let socket = socket_path()?;
-    if socket.exists() && !daemon_process_running() {
+    if socket.exists() && running_pid().is_none() {
         let _ = std::fs::remove_file(&socket);
     }
     Ok(())
 }
 
-fn daemon_process_running() -> bool {
-    let mut system = sysinfo::System::new();
-    system.refresh_processes(sysinfo::ProcessesToUpdate::All, true);
-    system.processes().values().any(|process| {
-        let cmd = process
-            .cmd()
-            .iter()
-            .map(|part| part.to_string_lossy().to_string())
-            .collect::<Vec<_>>()
-            .join(" ");
-        cmd.contains("servinel") && cmd.contains("daemon")
-    })
+/// Asks the daemon to stop its services and exit, then waits until it has.
+pub async fn shutdown_daemon() -> Result<()> {
+    if let Response::Error(error) = request_response(&Request::Shutdown).await? {
+        return Err(error.into());
+    }
+    for _ in 0..DAEMON_RETRY_ATTEMPTS {
+        if running_pid().is_none() {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(DAEMON_RETRY_DELAY_MS)).await;
+    }
+    Err(ServinelError::Usage("daemon did not exit after shutdown".to_string()))
 }
 
 fn spawn_daemon() -> Result<()> {
@@ -120,16 +204,177 @@ pub async fn stream_logs(
         match response {
             Response::LogChunk(chunk) => on_chunk(chunk),
             Response::Ack => break,
-            Response::Error(message) => return Err(ServinelError::Usage(message)),
+            Response::Error(error) => return Err(error.into()),
             _ => {}
         }
     }
     Ok(())
 }
 
-async fn write_request(stream: &mut UnixStream, request: &Request) -> Result<()> {
+async fn write_request(stream: &mut (impl AsyncWrite + Unpin), request: &impl Serialize) -> Result<()> {
     let payload = serde_json::to_string(request)?;
     stream.write_all(payload.as_bytes()).await?;
     stream.write_all(b"\n").await?;
     Ok(())
 }
+
+/// Senders for the responses of each request in flight, or `None` once the
+/// daemon has hung up
+type Routes = Arc<Mutex<Option<HashMap<u64, mpsc::UnboundedSender<Response>>>>>;
+
+/// A long-lived connection to the daemon, shared by any number of requests
+/// and log streams at once. Cloning it is cheap and shares the connection.
+#[derive(Clone)]
+pub struct Connection {
+    frames: mpsc::UnboundedSender<ClientFrame>,
+    routes: Routes,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Connection {
+    pub async fn open() -> Result<Self> {
+        let (read, mut write) = connect().await?.into_split();
+
+        let (frames, mut outgoing) = mpsc::unbounded_channel::<ClientFrame>();
+        tokio::spawn(async move {
+            while let Some(frame) = outgoing.recv().await {
+                if write_request(&mut write, &frame).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let routes: Routes = Arc::new(Mutex::new(Some(HashMap::new())));
+        let incoming = routes.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(read).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let frame = match serde_json::from_str::<ServerFrame>(&line) {
+                    Ok(frame) => frame,
+                    // A bad frame says nothing about the other calls in flight
+                    Err(err) => {
+                        tracing::warn!(%err, "ipc: skipping malformed frame");
+                        continue;
+                    }
+                };
+                let mut routes = incoming.lock().unwrap();
+                let Some(routes) = routes.as_mut() else {
+                    break;
+                };
+                match frame {
+                    ServerFrame::Response { id, response } => {
+                        if let Some(route) = routes.get(&id) {
+                            let _ = route.send(response);
+                        }
+                    }
+                    ServerFrame::End { id } => {
+                        routes.remove(&id);
+                    }
+                }
+            }
+            // Dropping the senders ends every call still waiting
+            incoming.lock().unwrap().take();
+        });
+
+        Ok(Self {
+            frames,
+            routes,
+            next_id: Arc::new(AtomicU64::new(1)),
+        })
+    }
+
+    /// Whether the daemon has hung up, so a new connection is needed.
+    pub fn is_closed(&self) -> bool {
+        self.routes.lock().unwrap().is_none()
+    }
+
+    /// Sends `request` without waiting for a response.
+    pub fn call(&self, request: Request) -> Result<Call> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, responses) = mpsc::unbounded_channel();
+        self.routes
+            .lock()
+            .unwrap()
+            .as_mut()
+            .ok_or(ServinelError::DaemonNotRunning)?
+            .insert(id, tx);
+        let call = Call {
+            id,
+            responses,
+            frames: self.frames.clone(),
+            routes: self.routes.clone(),
+            ended: false,
+        };
+        self.frames
+            .send(ClientFrame::Request { id, request })
+            .map_err(|_| ServinelError::DaemonNotRunning)?;
+        Ok(call)
+    }
+
+    /// Like [`request_response`], over this connection.
+    pub async fn request(&self, request: Request) -> Result<Response> {
+        let mut call = self.call(request)?;
+        let response = call.next().await.ok_or(ServinelError::DaemonNotRunning)?;
+        // Wait for the end marker, which follows straight away, so the call
+        // is not cancelled needlessly when dropped
+        while call.next().await.is_some() {}
+        Ok(response)
+    }
+
+    /// Like [`stream_logs`], over this connection.
+    pub async fn stream_logs(&self, request: Request, mut on_chunk: impl FnMut(LogChunk)) -> Result<()> {
+        let mut call = self.call(request)?;
+        while let Some(response) = call.next().await {
+            match response {
+                Response::LogChunk(chunk) => on_chunk(chunk),
+                Response::Ack => break,
+                Response::Error(error) => return Err(error.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The responses to one request on a [`Connection`]. Dropping it before the
+/// last response cancels the request.
+pub struct Call {
+    id: u64,
+    responses: mpsc::UnboundedReceiver<Response>,
+    frames: mpsc::UnboundedSender<ClientFrame>,
+    routes: Routes,
+    ended: bool,
+}
+
+impl Call {
+    /// The next response, or `None` after the last one.
+    pub async fn next(&mut self) -> Option<Response> {
+        let response = self.responses.recv().await;
+        self.ended = response.is_none();
+        response
+    }
+
+    /// The next response if one has already arrived, without waiting.
+    pub fn try_next(&mut self) -> Option<Response> {
+        match self.responses.try_recv() {
+            Ok(response) => Some(response),
+            Err(TryRecvError::Disconnected) => {
+                self.ended = true;
+                None
+            }
+            Err(TryRecvError::Empty) => None,
+        }
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        if self.ended {
+            return;
+        }
+        if let Some(routes) = self.routes.lock().unwrap().as_mut() {
+            routes.remove(&self.id);
+        }
+        let _ = self.frames.send(ClientFrame::Cancel { id: self.id });
+    }
+}
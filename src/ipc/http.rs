@@ -0,0 +1,452 @@
+//! Optional HTTP/JSON API offering the same operations as the Unix socket,
+//! for clients that would rather not speak its newline-delimited framing.
+//! Enabled by `SERVINEL_HTTP_ADDR` when the daemon starts.
+
+use std::convert::Infallible;
+use std::fmt;
+use std::fs::Permissions;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::{Query, Request as HttpRequest, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response as HttpResponse};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::mpsc;
+
+use crate::daemon::Daemon;
+use crate::error::{ErrorKind, Result, ServinelError};
+use crate::ipc::protocol::{
+    ErrorResponse, LogRun, PROTOCOL_VERSION, Request, Response, ServiceSelector, Topic,
+};
+use crate::ipc::server::{handle_request, stream_logs, RESPONSE_QUEUE};
+use crate::util::{http_token_path, parse_duration};
+
+/// Where the API listens
+pub enum HttpAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl HttpAddr {
+    /// Reads `SERVINEL_HTTP_ADDR`: `127.0.0.1:PORT` (or another loopback
+    /// address), or `unix:PATH` for a socket of its own.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(value) = std::env::var("SERVINEL_HTTP_ADDR") else {
+            return Ok(None);
+        };
+        if let Some(path) = value.strip_prefix("unix:") {
+            return Ok(Some(HttpAddr::Unix(PathBuf::from(path))));
+        }
+        let addr: SocketAddr = value.parse().map_err(|_| {
+            ServinelError::Usage(format!(
+                "invalid SERVINEL_HTTP_ADDR '{value}', expected HOST:PORT or unix:PATH"
+            ))
+        })?;
+        if !addr.ip().is_loopback() {
+            return Err(ServinelError::Usage(format!(
+                "SERVINEL_HTTP_ADDR must be a loopback address, not {}",
+                addr.ip()
+            )));
+        }
+        Ok(Some(HttpAddr::Tcp(addr)))
+    }
+}
+
+impl fmt::Display for HttpAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpAddr::Tcp(addr) => write!(f, "http://{addr}"),
+            HttpAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+pub enum HttpListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+pub async fn bind(addr: &HttpAddr) -> Result<HttpListener> {
+    match addr {
+        HttpAddr::Tcp(addr) => Ok(HttpListener::Tcp(TcpListener::bind(addr).await?)),
+        HttpAddr::Unix(path) => {
+            // Only a socket left by an earlier daemon is replaced, never a
+            // file that happens to be at a mistyped path
+            match std::fs::symlink_metadata(path) {
+                Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+                Ok(_) => {
+                    return Err(ServinelError::Usage(format!(
+                        "SERVINEL_HTTP_ADDR points at {}, which is not a socket",
+                        path.display()
+                    )));
+                }
+                Err(_) => {}
+            }
+            Ok(HttpListener::Unix(UnixListener::bind(path)?))
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ApiState {
+    daemon: Arc<Daemon>,
+    token: Arc<str>,
+}
+
+pub async fn serve(listener: HttpListener, daemon: Arc<Daemon>) -> Result<()> {
+    let state = ApiState {
+        daemon,
+        token: load_or_create_token()?.into(),
+    };
+    let app = Router::new()
+        .route("/v1/hello", get(hello))
+        .route("/v1/status", get(status))
+        .route("/v1/profiles", get(profiles))
+        .route("/v1/inspect", get(inspect))
+        .route("/v1/start", post(start))
+        .route("/v1/stop", post(stop))
+        .route("/v1/restart", post(restart))
+        .route("/v1/logs", get(logs))
+        .route("/v1/events", get(events))
+        .layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state);
+    match listener {
+        HttpListener::Tcp(listener) => axum::serve(listener, app).await?,
+        HttpListener::Unix(listener) => axum::serve(listener, app).await?,
+    }
+    Ok(())
+}
+
+/// Reads the API token, generating one on first use. The file is left
+/// readable by the user only. Delete it and restart the daemon to change it.
+fn load_or_create_token() -> Result<String> {
+    let path = http_token_path()?;
+    match std::fs::read_to_string(&path) {
+        Ok(token) if !token.trim().is_empty() => {
+            std::fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+            return Ok(token.trim().to_string());
+        }
+        Ok(_) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    let mut bytes = [0u8; 32];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    let token: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)?;
+    // `mode` only applies when the file is created
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    file.write_all(token.as_bytes())?;
+    Ok(token)
+}
+
+async fn require_token(
+    State(state): State<ApiState>,
+    request: HttpRequest,
+    next: Next,
+) -> HttpResponse {
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if presented.is_some_and(|token| same_token(token, &state.token)) {
+        return next.run(request).await;
+    }
+    let error = ErrorResponse {
+        kind: ErrorKind::Usage,
+        message: format!("missing or wrong bearer token, see {}", token_hint()),
+        name: None,
+        candidates: Vec::new(),
+    };
+    (StatusCode::UNAUTHORIZED, Json(error)).into_response()
+}
+
+fn token_hint() -> String {
+    http_token_path()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| "the data directory".to_string())
+}
+
+/// Compares in constant time, so the token cannot be guessed byte by byte
+fn same_token(presented: &str, token: &str) -> bool {
+    presented.len() == token.len()
+        && presented
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Which services a request is about: a comma-separated `service` list, a
+/// `profile`, or every service of the app
+#[derive(Deserialize)]
+struct Target {
+    app: Option<String>,
+    service: Option<String>,
+    profile: Option<String>,
+}
+
+impl Target {
+    fn selector(&self) -> ServiceSelector {
+        match (&self.service, &self.profile) {
+            (Some(service), _) if service.contains(',') => {
+                ServiceSelector::Services(service.split(',').map(str::to_string).collect())
+            }
+            (Some(service), _) => ServiceSelector::Service(service.clone()),
+            (None, Some(profile)) => ServiceSelector::Profile(profile.clone()),
+            (None, None) => ServiceSelector::All,
+        }
+    }
+}
+
+async fn hello(State(state): State<ApiState>) -> HttpResponse {
+    Json(
+        state
+            .daemon
+            .hello(env!("CARGO_PKG_VERSION"), PROTOCOL_VERSION),
+    )
+    .into_response()
+}
+
+async fn status(State(state): State<ApiState>, Query(target): Query<Target>) -> HttpResponse {
+    let selector = target.selector();
+    call(
+        &state,
+        Request::Status {
+            app: target.app,
+            selector,
+        },
+    )
+    .await
+}
+
+async fn profiles(State(state): State<ApiState>, Query(target): Query<Target>) -> HttpResponse {
+    call(&state, Request::Profiles { app: target.app }).await
+}
+
+#[derive(Deserialize)]
+struct InspectQuery {
+    app: Option<String>,
+    service: String,
+}
+
+async fn inspect(State(state): State<ApiState>, Query(query): Query<InspectQuery>) -> HttpResponse {
+    let request = Request::Inspect {
+        app: query.app,
+        service: query.service,
+    };
+    call(&state, request).await
+}
+
+async fn start(State(state): State<ApiState>, Query(target): Query<Target>) -> HttpResponse {
+    let selector = target.selector();
+    let request = Request::Start {
+        file: None,
+        overrides: Vec::new(),
+        app: target.app,
+        selector,
+        client: None,
+    };
+    call(&state, request).await
+}
+
+#[derive(Deserialize)]
+struct StopQuery {
+    #[serde(flatten)]
+    target: Target,
+    /// Grace period before SIGKILL, such as `5s`
+    timeout: Option<String>,
+}
+
+async fn stop(State(state): State<ApiState>, Query(query): Query<StopQuery>) -> HttpResponse {
+    let timeout = match query.timeout.as_deref().map(parse_duration) {
+        Some(None) => return usage_error("invalid timeout, expected a duration such as 5s"),
+        Some(Some(timeout)) => Some(timeout),
+        None => None,
+    };
+    let selector = query.target.selector();
+    let request = Request::Stop {
+        app: query.target.app,
+        selector,
+        timeout,
+    };
+    call(&state, request).await
+}
+
+async fn restart(State(state): State<ApiState>, Query(target): Query<Target>) -> HttpResponse {
+    let selector = target.selector();
+    call(
+        &state,
+        Request::Restart {
+            app: target.app,
+            selector,
+        },
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+struct LogsQuery {
+    #[serde(flatten)]
+    target: Target,
+    tail: Option<usize>,
+    #[serde(default)]
+    merged: bool,
+    run: Option<String>,
+    /// Keep the response open and send new lines as Server-Sent Events
+    #[serde(default)]
+    follow: bool,
+}
+
+async fn logs(State(state): State<ApiState>, Query(query): Query<LogsQuery>) -> HttpResponse {
+    let run = match query.run.as_deref().map(str::parse::<LogRun>) {
+        Some(Err(message)) => return usage_error(&message),
+        Some(Ok(run)) => Some(run),
+        None => None,
+    };
+    let selector = query.target.selector();
+    if query.follow {
+        // Asked for directly, so a bad selector is still a plain error
+        // response and an empty history does not hold the stream back
+        let (chunks, subs) = match state
+            .daemon
+            .logs(query.target.app, selector, query.tail, run, query.merged)
+            .await
+        {
+            Ok(result) => result,
+            Err(err) => return error_response((&err).into()),
+        };
+        let (tx, rx) = mpsc::channel(RESPONSE_QUEUE);
+        stream_logs(&tx, subs);
+        let history = futures::stream::iter(chunks.into_iter().map(Response::LogChunk));
+        return event_stream(history.chain(receiver_stream(rx)));
+    }
+    let request = Request::Logs {
+        app: query.target.app,
+        selector,
+        follow: false,
+        tail: query.tail,
+        merged: query.merged,
+        run,
+    };
+    let mut responses = spawn_request(&state, request);
+
+    let mut chunks = Vec::new();
+    while let Some(response) = responses.recv().await {
+        match response {
+            Response::LogChunk(chunk) => chunks.push(chunk),
+            Response::Error(error) => return error_response(error),
+            _ => break,
+        }
+    }
+    Json(chunks).into_response()
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    /// Comma-separated topics; all of them when absent
+    topics: Option<String>,
+}
+
+async fn events(State(state): State<ApiState>, Query(query): Query<EventsQuery>) -> HttpResponse {
+    let topics = match query.topics {
+        Some(topics) => match topics
+            .split(',')
+            .map(str::parse)
+            .collect::<std::result::Result<Vec<Topic>, _>>()
+        {
+            Ok(topics) => topics,
+            Err(message) => return usage_error(&message),
+        },
+        None => Topic::ALL.to_vec(),
+    };
+    let responses = spawn_request(&state, Request::Subscribe { topics });
+    event_stream(receiver_stream(responses))
+}
+
+/// Runs `request` as the socket server would, returning its responses.
+fn spawn_request(state: &ApiState, request: Request) -> mpsc::Receiver<Response> {
+    let (tx, rx) = mpsc::channel(RESPONSE_QUEUE);
+    tokio::spawn(handle_request(request, state.daemon.clone(), tx));
+    rx
+}
+
+/// Runs a request with a single response and sends it back as JSON.
+async fn call(state: &ApiState, request: Request) -> HttpResponse {
+    let Some(response) = spawn_request(state, request).recv().await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    match response {
+        Response::Ack | Response::DaemonShutdown => StatusCode::NO_CONTENT.into_response(),
+        Response::Error(error) => error_response(error),
+        Response::StatusSnapshot(snapshot) => Json(snapshot).into_response(),
+        Response::ProfilesList(profiles) => Json(profiles).into_response(),
+        Response::ServiceDetail(detail) => Json(detail).into_response(),
+        Response::LogChunk(chunk) => Json(chunk).into_response(),
+        Response::Event(event) => Json(event).into_response(),
+        Response::Hello(info) => Json(info).into_response(),
+    }
+}
+
+fn receiver_stream(responses: mpsc::Receiver<Response>) -> impl Stream<Item = Response> {
+    futures::stream::unfold(responses, |mut responses| async move {
+        responses.recv().await.map(|response| (response, responses))
+    })
+}
+
+/// Sends log lines as `log` events and daemon events under their topic's
+/// name, each with a JSON payload. Dropping the response, when the client
+/// goes away, ends the request.
+fn event_stream(responses: impl Stream<Item = Response> + Send + 'static) -> HttpResponse {
+    let events = responses.filter_map(|response| async move {
+        let event = match response {
+            Response::LogChunk(chunk) => SseEvent::default().event("log").json_data(chunk),
+            Response::Event(event) => SseEvent::default()
+                .event(event.topic().as_str())
+                .json_data(event),
+            _ => return None,
+        };
+        event.ok().map(Ok::<_, Infallible>)
+    });
+    Sse::new(events)
+        .keep_alive(KeepAlive::new().interval(Duration::from_secs(15)))
+        .into_response()
+}
+
+fn error_response(error: ErrorResponse) -> HttpResponse {
+    let status = match error.kind {
+        ErrorKind::AppNotFound
+        | ErrorKind::ServiceNotFound
+        | ErrorKind::ProfileNotFound
+        | ErrorKind::ComposeNotFound => StatusCode::NOT_FOUND,
+        ErrorKind::Usage | ErrorKind::InvalidCompose => StatusCode::BAD_REQUEST,
+        ErrorKind::Internal
+        | ErrorKind::DaemonNotRunning
+        | ErrorKind::DaemonAlreadyRunning
+        | ErrorKind::SpawnFailed => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, Json(error)).into_response()
+}
+
+fn usage_error(message: &str) -> HttpResponse {
+    error_response(ErrorResponse::from(&ServinelError::Usage(
+        message.to_string(),
+    )))
+}
@@ -1,10 +1,23 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
+use crate::compose::ServiceConfig;
+use crate::error::ErrorKind;
 use crate::logs::{LogEntry, LogStream};
 use crate::metrics::ServiceMetrics;
 
+/// Version of the request and response format. Bumped whenever a change
+/// would stop an older daemon or client from understanding a newer one.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// What this build of the daemon can do beyond the basic requests, reported
+/// in [`DaemonInfo::features`]
+pub const FEATURES: &[&str] = &["multiplex", "subscribe", "log-runs", "merged-logs"];
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ServiceSelector {
     All,
@@ -13,20 +26,51 @@ pub enum ServiceSelector {
     Profile(String),
 }
 
+/// A run of a service, relative to the one started last
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogRun {
+    Current,
+    Previous,
+}
+
+impl FromStr for LogRun {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "current" => Ok(LogRun::Current),
+            "previous" => Ok(LogRun::Previous),
+            _ => Err(format!("invalid run '{value}', expected 'current' or 'previous'")),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Request {
     Up {
         file: PathBuf,
+        /// Extra compose files merged on top of `file`, in order
+        #[serde(default)]
+        overrides: Vec<PathBuf>,
         profile: Option<String>,
+        #[serde(default)]
+        client: Option<ClientContext>,
     },
     Start {
         file: Option<PathBuf>,
+        #[serde(default)]
+        overrides: Vec<PathBuf>,
         app: Option<String>,
         selector: ServiceSelector,
+        #[serde(default)]
+        client: Option<ClientContext>,
     },
     Stop {
         app: Option<String>,
         selector: ServiceSelector,
+        /// Overrides each service's stop_grace_period
+        #[serde(default)]
+        timeout: Option<Duration>,
     },
     Restart {
         app: Option<String>,
@@ -42,24 +86,208 @@ pub enum Request {
         follow: bool,
         tail: Option<usize>,
         merged: bool,
+        /// Only this run of each service; all kept runs when `None`
+        #[serde(default)]
+        run: Option<LogRun>,
     },
     Profiles {
         app: Option<String>,
     },
+    Inspect {
+        app: Option<String>,
+        service: String,
+    },
     Down {
         app: Option<String>,
+        #[serde(default)]
+        timeout: Option<Duration>,
     },
     DashAttach,
+    /// Stops every service gracefully, then exits the daemon
+    Shutdown,
+    /// Streams an [`Event`] whenever something in `topics` changes
+    Subscribe {
+        topics: Vec<Topic>,
+    },
+    /// Sent before anything else to learn which daemon is listening
+    Hello {
+        client_version: String,
+        protocol_version: u32,
+    },
+}
+
+/// The daemon's answer to [`Request::Hello`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonInfo {
+    /// servinel version the daemon was built from
+    pub version: String,
+    pub protocol_version: u32,
+    pub pid: u32,
+    pub uptime_secs: u64,
+    pub features: Vec<String>,
+    /// Where the HTTP API listens, when it is enabled
+    #[serde(default)]
+    pub http: Option<String>,
+}
+
+/// Kinds of [`Event`] a subscriber can ask for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Topic {
+    /// A full snapshot on subscribing, then the apps that changed
+    Status,
+    /// Services starting and exiting
+    Lifecycle,
+    /// A live service becoming ready, unhealthy or ready again
+    Health,
+    /// Per-service CPU and memory, every time they are sampled
+    Metrics,
+}
+
+impl Topic {
+    pub const ALL: [Topic; 4] = [Topic::Status, Topic::Lifecycle, Topic::Health, Topic::Metrics];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Topic::Status => "status",
+            Topic::Lifecycle => "lifecycle",
+            Topic::Health => "health",
+            Topic::Metrics => "metrics",
+        }
+    }
+}
+
+impl FromStr for Topic {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Topic::ALL
+            .into_iter()
+            .find(|topic| topic.as_str() == value)
+            .ok_or_else(|| {
+                format!("invalid topic '{value}', expected 'status', 'lifecycle', 'health' or 'metrics'")
+            })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Event {
+    Snapshot(StatusSnapshot),
+    Delta(StatusDelta),
+    Started {
+        app: String,
+        service: String,
+        pid: Option<u32>,
+    },
+    /// The service stopped, exited or failed to start; `status` says which
+    Exited {
+        app: String,
+        service: String,
+        status: String,
+        exit_code: Option<i32>,
+        exit_signal: Option<i32>,
+    },
+    HealthChanged {
+        app: String,
+        service: String,
+        status: String,
+    },
+    Metrics {
+        app: String,
+        service: String,
+        metrics: ServiceMetrics,
+    },
+}
+
+impl Event {
+    pub fn topic(&self) -> Topic {
+        match self {
+            Event::Snapshot(_) | Event::Delta(_) => Topic::Status,
+            Event::Started { .. } | Event::Exited { .. } => Topic::Lifecycle,
+            Event::HealthChanged { .. } => Topic::Health,
+            Event::Metrics { .. } => Topic::Metrics,
+        }
+    }
+}
+
+/// Changes to a [`StatusSnapshot`]: every app with a changed service, in
+/// full, and the apps that were removed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusDelta {
+    pub apps: Vec<AppSnapshot>,
+    pub removed_apps: Vec<String>,
+    pub system_cpu: f32,
+    pub system_memory_used: u64,
+    pub system_memory_total: u64,
+}
+
+/// A message from a client on a multiplexed connection. The daemon tags its
+/// replies with the same `id`, so any number of requests and log streams can
+/// be in flight on one connection. A connection whose first line is a bare
+/// [`Request`] is served one-shot instead: its responses, then close.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ClientFrame {
+    Request { id: u64, request: Request },
+    /// Stops the responses to request `id`, such as a log follow
+    Cancel { id: u64 },
+}
+
+/// A message from the daemon on a multiplexed connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ServerFrame {
+    Response { id: u64, response: Response },
+    /// Request `id` has sent its last response
+    End { id: u64 },
+}
+
+/// Environment and working directory of the CLI invocation, so services can
+/// start from the caller's shell rather than the daemon's.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClientContext {
+    pub env: BTreeMap<String, String>,
+    pub cwd: Option<PathBuf>,
+}
+
+impl ClientContext {
+    pub fn capture() -> Self {
+        Self {
+            env: std::env::vars().collect(),
+            cwd: std::env::current_dir().ok(),
+        }
+    }
+
+    /// Resolves a path sent by the client against the client's cwd.
+    pub fn resolve_path(&self, path: PathBuf) -> PathBuf {
+        match &self.cwd {
+            Some(cwd) if path.is_relative() => cwd.join(path),
+            _ => path,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Response {
     Ack,
-    Error(String),
+    Error(ErrorResponse),
     StatusSnapshot(StatusSnapshot),
     ProfilesList(Vec<String>),
     LogChunk(LogChunk),
+    ServiceDetail(Box<ServiceDetail>),
     DaemonShutdown,
+    Event(Box<Event>),
+    Hello(DaemonInfo),
+}
+
+/// A failed request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// The app, service or profile that was not found
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Names that would have been found instead
+    #[serde(default)]
+    pub candidates: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -73,22 +301,76 @@ pub struct StatusSnapshot {
     pub system_memory_total: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl StatusSnapshot {
+    /// Brings the snapshot up to date with a [`StatusDelta`].
+    pub fn apply(&mut self, delta: StatusDelta) {
+        self.apps.retain(|app| !delta.removed_apps.contains(&app.app_name));
+        for app in delta.apps {
+            match self.apps.iter_mut().find(|known| known.app_name == app.app_name) {
+                Some(known) => *known = app,
+                None => self.apps.push(app),
+            }
+        }
+        self.system_cpu = delta.system_cpu;
+        self.system_memory_used = delta.system_memory_used;
+        self.system_memory_total = delta.system_memory_total;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AppSnapshot {
     pub app_name: String,
     pub services: Vec<ServiceSnapshot>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ServiceSnapshot {
     pub name: String,
     pub status: String,
     pub pid: Option<u32>,
     pub uptime_secs: Option<u64>,
     pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub exit_signal: Option<i32>,
+    #[serde(default)]
+    pub restart_count: u32,
+    /// Why the service failed to start, while its status is `failed`
+    #[serde(default)]
+    pub failure: Option<String>,
+    /// Time from spawn until the service first passed its readiness check
+    #[serde(default)]
+    pub time_to_ready: Option<Duration>,
+    /// Output bytes lost to line truncation and `\r` rewrites
+    #[serde(default)]
+    pub log_bytes_dropped: u64,
     pub metrics: ServiceMetrics,
 }
 
+impl ServiceSnapshot {
+    /// Exit code of the last run, or the signal that killed it
+    pub fn exit_description(&self) -> String {
+        match (self.exit_code, self.exit_signal) {
+            (Some(code), _) => code.to_string(),
+            (None, Some(signal)) => crate::util::signal_name(signal),
+            (None, None) => "-".to_string(),
+        }
+    }
+
+    /// Whether the service has a live process behind it
+    pub fn is_alive(&self) -> bool {
+        matches!(self.status.as_str(), "starting" | "running" | "unhealthy")
+    }
+}
+
+/// Resolved configuration and current state of a single service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceDetail {
+    pub app_name: String,
+    pub config: ServiceConfig,
+    pub working_directory: PathBuf,
+    pub snapshot: ServiceSnapshot,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogChunk {
     pub app: String,
@@ -96,20 +378,35 @@ pub struct LogChunk {
     pub entry: LogEntry,
 }
 
-pub fn format_log_entry(entry: &LogEntry, merged: bool, service: &str) -> String {
+/// How log timestamps are shown to users
+pub const LOG_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+/// ANSI colours for service prefixes in merged logs
+const SERVICE_COLORS: [u8; 6] = [36, 33, 32, 35, 34, 31];
+
+pub fn format_log_entry(entry: &LogEntry, service: &str) -> String {
     let prefix = match entry.stream {
         LogStream::Stdout => "stdout",
         LogStream::Stderr => "stderr",
+        LogStream::System => "servinel",
     };
-    
-    let time = chrono::DateTime::from_timestamp(entry.timestamp as i64, 0)
-        .map(|dt| dt.with_timezone(&chrono::Local))
-        .unwrap_or_default();
-    let time_str = time.format("%Y-%m-%d %H:%M:%S");
-
-    if merged {
-        format!("[{}] [{}] {}", time_str, service, entry.line)
+    format!("[{}] [{}:{}] {}", format_log_time(entry), service, prefix, entry.line)
+}
+
+/// A line of merged output, docker-compose style: the service name padded
+/// to `width`, in a colour derived from the name so it stays the same
+/// between runs.
+pub fn format_merged_log_entry(entry: &LogEntry, service: &str, width: usize, color: bool) -> String {
+    let name = format!("{service:<width$} |");
+    let name = if color {
+        let hash = service.bytes().fold(0usize, |hash, byte| hash.wrapping_mul(31).wrapping_add(byte as usize));
+        format!("\x1b[{}m{name}\x1b[0m", SERVICE_COLORS[hash % SERVICE_COLORS.len()])
     } else {
-        format!("[{}] [{}:{}] {}", time_str, service, prefix, entry.line)
-    }
+        name
+    };
+    format!("{} [{}] {}", name, format_log_time(entry), entry.line)
+}
+
+fn format_log_time(entry: &LogEntry) -> impl std::fmt::Display {
+    entry.timestamp.with_timezone(&chrono::Local).format(LOG_TIME_FORMAT)
 }
@@ -1,12 +1,22 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::{UnixListener, UnixStream};
-use tokio::sync::mpsc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinSet;
 
 use crate::daemon::{Daemon, LogSubscription};
-use crate::error::{Result, ServinelError};
-use crate::ipc::protocol::{LogChunk, Request, Response};
+use crate::error::Result;
+use crate::ipc::protocol::{ClientFrame, Event, LogChunk, Request, Response, ServerFrame, Topic};
+use crate::logs::{LogEntry, LogStream};
+
+/// Responses queued per request, and frames per connection, before the client
+/// has to catch up. Followed log lines beyond this are dropped and counted.
+pub const RESPONSE_QUEUE: usize = 1024;
 
 pub async fn serve(listener: UnixListener, daemon: Arc<Daemon>) -> Result<()> {
     loop {
@@ -21,62 +31,171 @@ pub async fn serve(listener: UnixListener, daemon: Arc<Daemon>) -> Result<()> {
 }
 
 async fn handle_connection(stream: UnixStream, daemon: Arc<Daemon>) -> Result<()> {
-    let (read, mut write) = stream.into_split();
-    let mut reader = BufReader::new(read);
-    let mut line = String::new();
-    let bytes = reader.read_line(&mut line).await?;
-    if bytes == 0 {
+    let (read, write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    let Some(line) = lines.next_line().await? else {
         return Ok(());
+    };
+    if let Ok(frame) = serde_json::from_str::<ClientFrame>(&line) {
+        return serve_multiplexed(frame, lines, write, daemon).await;
+    }
+    let request: Request = serde_json::from_str(&line)?;
+    serve_one_shot(request, write, daemon).await
+}
+
+/// Answers a single request, then closes the connection.
+async fn serve_one_shot(request: Request, mut write: OwnedWriteHalf, daemon: Arc<Daemon>) -> Result<()> {
+    let (tx, mut rx) = mpsc::channel(RESPONSE_QUEUE);
+    tokio::spawn(handle_request(request, daemon, tx));
+    while let Some(response) = rx.recv().await {
+        write_message(&mut write, &response).await?;
     }
-    let request: Request = serde_json::from_str(line.trim_end())?;
+    Ok(())
+}
+
+/// Serves frames until the client hangs up, running each request as its own
+/// task. Lines that are not a valid frame are logged and skipped. Cancelling
+/// a request, or hanging up, stops its responses; work it already started,
+/// such as starting services, carries on.
+async fn serve_multiplexed(
+    first: ClientFrame,
+    mut lines: Lines<BufReader<OwnedReadHalf>>,
+    write: OwnedWriteHalf,
+    daemon: Arc<Daemon>,
+) -> Result<()> {
+    let (frames, outgoing) = mpsc::channel(RESPONSE_QUEUE);
+    tokio::spawn(write_frames(write, outgoing));
+
+    let mut tasks = JoinSet::new();
+    let mut in_flight = HashMap::new();
+    let mut next = Some(first);
+    while let Some(frame) = next {
+        match frame {
+            ClientFrame::Request { id, request } => {
+                let (tx, mut rx) = mpsc::channel(RESPONSE_QUEUE);
+                tokio::spawn(handle_request(request, daemon.clone(), tx));
+                let frames = frames.clone();
+                let forward = tasks.spawn(async move {
+                    while let Some(response) = rx.recv().await {
+                        if frames.send(ServerFrame::Response { id, response }).await.is_err() {
+                            return;
+                        }
+                    }
+                    let _ = frames.send(ServerFrame::End { id }).await;
+                });
+                in_flight.insert(id, forward);
+            }
+            ClientFrame::Cancel { id } => {
+                if let Some(forward) = in_flight.remove(&id) {
+                    forward.abort();
+                }
+            }
+        }
+        while tasks.try_join_next().is_some() {}
+        in_flight.retain(|_, forward| !forward.is_finished());
+
+        next = None;
+        while let Some(line) = lines.next_line().await? {
+            match serde_json::from_str(&line) {
+                Ok(frame) => {
+                    next = Some(frame);
+                    break;
+                }
+                // A bad frame says nothing about the streams already running
+                Err(err) => tracing::warn!(%err, "ipc: skipping malformed frame"),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes frames as they are queued, flushing once the queue is drained.
+async fn write_frames(write: OwnedWriteHalf, mut outgoing: mpsc::Receiver<ServerFrame>) {
+    let mut write = BufWriter::new(write);
+    while let Some(frame) = outgoing.recv().await {
+        if write_message(&mut write, &frame).await.is_err()
+            || (outgoing.is_empty() && write.flush().await.is_err())
+        {
+            return;
+        }
+    }
+}
+
+/// Runs `request`, sending its responses to `out`. Log follows keep sending
+/// after this returns, until `out` is closed.
+pub async fn handle_request(request: Request, daemon: Arc<Daemon>, out: mpsc::Sender<Response>) {
     tracing::info!(?request, "ipc: received request");
     match request {
-        Request::Up { file, profile } => {
-            if let Err(err) = daemon.up(file.clone(), profile.clone()).await {
+        Request::Up {
+            file,
+            overrides,
+            profile,
+            client,
+        } => {
+            if let Err(err) = daemon
+                .up(file.clone(), overrides, profile.clone(), client)
+                .await
+            {
                 tracing::error!(?err, ?file, ?profile, "daemon up failed");
-                write_response(&mut write, &Response::Error(err.to_string())).await?;
-                return Ok(());
+                let _ = out.send(Response::Error((&err).into())).await;
+                return;
             }
-            write_response(&mut write, &Response::Ack).await?;
+            let _ = out.send(Response::Ack).await;
         }
-        Request::Start { file, app, selector } => {
-            if let Err(err) = daemon.start(file, app, selector).await {
-                write_response(&mut write, &Response::Error(err.to_string())).await?;
-                return Ok(());
+        Request::Start {
+            file,
+            overrides,
+            app,
+            selector,
+            client,
+        } => {
+            if let Err(err) = daemon.start(file, overrides, app, selector, client).await {
+                let _ = out.send(Response::Error((&err).into())).await;
+                return;
             }
-            write_response(&mut write, &Response::Ack).await?;
+            let _ = out.send(Response::Ack).await;
         }
-        Request::Stop { app, selector } => {
-            if let Err(err) = daemon.stop(app, selector).await {
-                write_response(&mut write, &Response::Error(err.to_string())).await?;
-                return Ok(());
+        Request::Stop { app, selector, timeout } => {
+            if let Err(err) = daemon.stop(app, selector, timeout).await {
+                let _ = out.send(Response::Error((&err).into())).await;
+                return;
             }
-            write_response(&mut write, &Response::Ack).await?;
+            let _ = out.send(Response::Ack).await;
         }
         Request::Restart { app, selector } => {
             if let Err(err) = daemon.restart(app, selector).await {
-                write_response(&mut write, &Response::Error(err.to_string())).await?;
-                return Ok(());
+                let _ = out.send(Response::Error((&err).into())).await;
+                return;
             }
-            write_response(&mut write, &Response::Ack).await?;
+            let _ = out.send(Response::Ack).await;
         }
         Request::Status { app, selector } => {
             match daemon.status(app, selector).await {
                 Ok(snapshot) => {
-                    write_response(&mut write, &Response::StatusSnapshot(snapshot)).await?;
+                    let _ = out.send(Response::StatusSnapshot(snapshot)).await;
                 }
                 Err(err) => {
-                    write_response(&mut write, &Response::Error(err.to_string())).await?;
+                    let _ = out.send(Response::Error((&err).into())).await;
                 }
             }
         }
         Request::Profiles { app } => {
             match daemon.profiles(app).await {
                 Ok(profiles) => {
-                    write_response(&mut write, &Response::ProfilesList(profiles)).await?;
+                    let _ = out.send(Response::ProfilesList(profiles)).await;
+                }
+                Err(err) => {
+                    let _ = out.send(Response::Error((&err).into())).await;
+                }
+            }
+        }
+        Request::Inspect { app, service } => {
+            match daemon.inspect(app, service).await {
+                Ok(detail) => {
+                    let _ = out.send(Response::ServiceDetail(Box::new(detail))).await;
                 }
                 Err(err) => {
-                    write_response(&mut write, &Response::Error(err.to_string())).await?;
+                    let _ = out.send(Response::Error((&err).into())).await;
                 }
             }
         }
@@ -85,78 +204,147 @@ async fn handle_connection(stream: UnixStream, daemon: Arc<Daemon>) -> Result<()
             selector,
             follow,
             tail,
-            merged: _,
+            merged,
+            run,
         } => {
-            let (chunks, subs) = match daemon.logs(app, selector, tail).await {
+            let (chunks, subs) = match daemon.logs(app, selector, tail, run, merged).await {
                 Ok(result) => result,
                 Err(err) => {
-                    write_response(&mut write, &Response::Error(err.to_string())).await?;
-                    return Ok(());
+                    let _ = out.send(Response::Error((&err).into())).await;
+                    return;
                 }
             };
             for chunk in chunks {
-                write_response(&mut write, &Response::LogChunk(chunk)).await?;
+                let _ = out.send(Response::LogChunk(chunk)).await;
             }
             if follow {
-                stream_logs(write, subs).await?;
+                stream_logs(&out, subs);
             } else {
-                write_response(&mut write, &Response::Ack).await?;
+                let _ = out.send(Response::Ack).await;
+            }
+        }
+        Request::Subscribe { topics } => {
+            let (snapshot, receiver) = daemon.subscribe().await;
+            if topics.contains(&Topic::Status) {
+                let _ = out.send(Response::Event(Box::new(Event::Snapshot(snapshot)))).await;
             }
+            stream_events(out, topics, receiver, daemon);
+        }
+        Request::Hello {
+            client_version,
+            protocol_version,
+        } => {
+            let _ = out.send(Response::Hello(daemon.hello(&client_version, protocol_version))).await;
         }
         Request::DashAttach => {
-            write_response(&mut write, &Response::Ack).await?;
+            let _ = out.send(Response::Ack).await;
+        }
+        Request::Shutdown => {
+            daemon.shutdown().await;
+            let _ = out.send(Response::DaemonShutdown).await;
         }
-        Request::Down { app } => {
-            match daemon.down(app).await {
+        Request::Down { app, timeout } => {
+            match daemon.down(app, timeout).await {
                 Ok(true) => {
-                    write_response(&mut write, &Response::DaemonShutdown).await?;
+                    let _ = out.send(Response::DaemonShutdown).await;
                 }
                 Ok(false) => {
-                    write_response(&mut write, &Response::Ack).await?;
+                    let _ = out.send(Response::Ack).await;
                 }
                 Err(err) => {
-                    write_response(&mut write, &Response::Error(err.to_string())).await?;
+                    let _ = out.send(Response::Error((&err).into())).await;
                 }
             }
         }
     }
-
-    Ok(())
 }
 
-async fn stream_logs(
-    mut write: tokio::net::unix::OwnedWriteHalf,
-    subs: Vec<LogSubscription>,
-) -> Result<()> {
-    let (tx, mut rx) = mpsc::unbounded_channel::<LogChunk>();
+/// Passes new lines from `subs` on to `out` until it is closed.
+pub fn stream_logs(out: &mpsc::Sender<Response>, subs: Vec<LogSubscription>) {
     for mut sub in subs {
-        let tx = tx.clone();
+        let out = out.clone();
         tokio::spawn(async move {
-            while let Ok(entry) = sub.receiver.recv().await {
-                let _ = tx.send(LogChunk {
-                    app: sub.app.clone(),
-                    service: sub.service.clone(),
-                    entry,
-                });
+            // Lines missed by falling behind the service, or because the client
+            // reads too slowly; reported once there is room again
+            let mut dropped = 0;
+            loop {
+                let entry = tokio::select! {
+                    _ = out.closed() => break,
+                    received = sub.receiver.recv() => match received {
+                        Ok(entry) => entry,
+                        Err(RecvError::Lagged(missed)) => {
+                            dropped += missed;
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                };
+                if dropped > 0 {
+                    let notice = LogEntry {
+                        timestamp: chrono::Utc::now(),
+                        stream: LogStream::System,
+                        line: format!("--- {dropped} lines dropped ---"),
+                        run: sub.run,
+                        source_timestamp: None,
+                    };
+                    match out.try_send(log_chunk(&sub, notice)) {
+                        Ok(()) => dropped = 0,
+                        Err(TrySendError::Full(_)) => {
+                            dropped += 1;
+                            continue;
+                        }
+                        Err(TrySendError::Closed(_)) => break,
+                    }
+                }
+                sub.run = entry.run;
+                match out.try_send(log_chunk(&sub, entry)) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => dropped += 1,
+                    Err(TrySendError::Closed(_)) => break,
+                }
             }
         });
     }
-    drop(tx);
+}
 
-    while let Some(chunk) = rx.recv().await {
-        if let Err(err) = write_response(&mut write, &Response::LogChunk(chunk)).await {
-            tracing::error!("Failed to write log chunk: {err}");
-            return Err(ServinelError::DaemonNotRunning);
+fn log_chunk(sub: &LogSubscription, entry: LogEntry) -> Response {
+    Response::LogChunk(LogChunk {
+        app: sub.app.clone(),
+        service: sub.service.clone(),
+        entry,
+    })
+}
+
+fn stream_events(
+    out: mpsc::Sender<Response>,
+    topics: Vec<Topic>,
+    mut receiver: tokio::sync::broadcast::Receiver<Event>,
+    daemon: Arc<Daemon>,
+) {
+    tokio::spawn(async move {
+        loop {
+            let event = tokio::select! {
+                _ = out.closed() => break,
+                received = receiver.recv() => match received {
+                    Ok(event) if topics.contains(&event.topic()) => event,
+                    Ok(_) => continue,
+                    // Deltas were missed, so start the subscriber over
+                    Err(RecvError::Lagged(_)) if topics.contains(&Topic::Status) => {
+                        Event::Snapshot(daemon.full_status().await)
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+            };
+            if out.send(Response::Event(Box::new(event))).await.is_err() {
+                break;
+            }
         }
-    }
-    Ok(())
+    });
 }
 
-async fn write_response(
-    write: &mut tokio::net::unix::OwnedWriteHalf,
-    response: &Response,
-) -> Result<()> {
-    let payload = serde_json::to_string(response)?;
+async fn write_message(write: &mut (impl AsyncWrite + Unpin), message: &impl Serialize) -> Result<()> {
+    let payload = serde_json::to_string(message)?;
     write.write_all(payload.as_bytes()).await?;
     write.write_all(b"\n").await?;
     Ok(())
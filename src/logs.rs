@@ -1,61 +1,650 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum LogStream {
     Stdout,
     Stderr,
+    /// Written by servinel itself, such as the marker between two runs
+    System,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEntry {
-    pub timestamp: u64,
+    /// When the line was captured
+    #[serde(deserialize_with = "timestamp::deserialize")]
+    pub timestamp: DateTime<Utc>,
     pub stream: LogStream,
     pub line: String,
+    /// Which start of the service produced the line, counting from 1
+    #[serde(default)]
+    pub run: u32,
+    /// When the service says it logged the line, read with `timestamp_format`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_timestamp: Option<DateTime<Utc>>,
 }
 
+/// Timestamps are written as RFC 3339; entries from older versions hold
+/// whole Unix seconds.
+mod timestamp {
+    use chrono::{DateTime, Utc};
+    use serde::{de::Error, Deserialize, Deserializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Seconds(i64),
+        Time(DateTime<Utc>),
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        match Repr::deserialize(deserializer)? {
+            Repr::Seconds(secs) => {
+                DateTime::from_timestamp(secs, 0).ok_or_else(|| D::Error::custom("timestamp out of range"))
+            }
+            Repr::Time(time) => Ok(time),
+        }
+    }
+}
+
+/// Reads a service's own timestamp from a log line: the first capture group
+/// of `format`, as RFC 3339 or ISO 8601 (local time without an offset), or
+/// as Unix time in seconds or milliseconds.
+pub fn extract_timestamp(format: &Regex, line: &str) -> Option<DateTime<Utc>> {
+    let text = format.captures(line)?.get(1)?.as_str().trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.with_timezone(&Utc));
+    }
+    for pattern in ["%Y-%m-%d %H:%M:%S%.f%#z", "%Y-%m-%dT%H:%M:%S%.f%#z"] {
+        if let Ok(time) = DateTime::parse_from_str(text, pattern) {
+            return Some(time.with_timezone(&Utc));
+        }
+    }
+    for pattern in ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(text, pattern) {
+            return Local.from_local_datetime(&naive).earliest().map(|time| time.with_timezone(&Utc));
+        }
+    }
+    let epoch: f64 = text.parse().ok()?;
+    let micros = if epoch >= 1e11 { epoch * 1e3 } else { epoch * 1e6 };
+    DateTime::from_timestamp_micros(micros as i64)
+}
+
+/// In-memory logs of the last few runs of a service, each capped at
+/// `capacity` lines so a chatty run cannot push out the one before it.
 #[derive(Debug, Clone)]
 pub struct LogBuffer {
-    entries: VecDeque<LogEntry>,
+    runs: VecDeque<(u32, VecDeque<LogEntry>)>,
     capacity: usize,
+    max_runs: usize,
 }
 
 impl LogBuffer {
-    pub fn new(capacity: usize) -> Self {
+    pub fn new(capacity: usize, max_runs: usize) -> Self {
         Self {
-            entries: VecDeque::with_capacity(capacity),
+            runs: VecDeque::new(),
             capacity,
+            max_runs: max_runs.max(1),
         }
     }
 
-    pub fn push(&mut self, entry: LogEntry) {
-        if self.entries.len() == self.capacity {
-            self.entries.pop_front();
+    /// Opens the buffer for `run`, dropping the oldest run if too many are kept.
+    pub fn start_run(&mut self, run: u32) {
+        if self.runs.back().is_some_and(|(last, _)| *last >= run) {
+            return;
+        }
+        self.runs.push_back((run, VecDeque::with_capacity(self.capacity)));
+        while self.runs.len() > self.max_runs {
+            self.runs.pop_front();
         }
-        self.entries.push_back(entry);
     }
 
-    pub fn tail(&self, count: usize) -> Vec<LogEntry> {
-        let count = count.min(self.entries.len());
-        self.entries
-            .iter()
-            .skip(self.entries.len() - count)
-            .cloned()
-            .collect()
+    pub fn current_run(&self) -> Option<u32> {
+        self.runs.back().map(|(run, _)| *run)
     }
 
-    pub fn all(&self) -> Vec<LogEntry> {
-        self.entries.iter().cloned().collect()
+    /// Adds a line to the buffer of its run. Late lines from a run that was
+    /// already dropped are discarded.
+    pub fn push(&mut self, entry: LogEntry) {
+        self.start_run(entry.run);
+        let Some((_, entries)) = self.runs.iter_mut().rev().find(|(run, _)| *run == entry.run) else {
+            return;
+        };
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
     }
 
-    pub fn clear(&mut self) {
-        self.entries.clear();
+    /// Lines of `run`, or of every kept run, limited to the last `tail`.
+    pub fn entries(&self, run: Option<u32>, tail: Option<usize>) -> Vec<LogEntry> {
+        let entries: Vec<&LogEntry> = self
+            .runs
+            .iter()
+            .filter(|(id, _)| run.is_none_or(|run| run == *id))
+            .flat_map(|(_, entries)| entries)
+            .collect();
+        let skip = tail.map_or(0, |tail| entries.len().saturating_sub(tail));
+        entries.into_iter().skip(skip).cloned().collect()
     }
 }
 
+/// Lines kept in memory per run of a service
+const LOG_BUFFER_CAPACITY: usize = 1000;
+/// Runs of a service whose output is kept in memory, the current one included
+const LOG_RUNS_KEPT: usize = 3;
+
 impl Default for LogBuffer {
     fn default() -> Self {
-        Self::new(1000)
+        Self::new(LOG_BUFFER_CAPACITY, LOG_RUNS_KEPT)
+    }
+}
+
+/// Lines a follower may fall behind before it misses some
+const FOLLOW_CAPACITY: usize = 1024;
+
+/// Logs of one service across all of its runs: the in-memory buffer and the
+/// channel followers subscribe to, which outlives restarts.
+#[derive(Debug)]
+pub struct ServiceLogs {
+    pub buffer: LogBuffer,
+    sender: broadcast::Sender<LogEntry>,
+}
+
+impl Default for ServiceLogs {
+    fn default() -> Self {
+        Self {
+            buffer: LogBuffer::default(),
+            sender: broadcast::channel(FOLLOW_CAPACITY).0,
+        }
+    }
+}
+
+impl ServiceLogs {
+    /// Keeps a line in memory and passes it to followers.
+    pub fn record(&mut self, entry: LogEntry) {
+        self.notify(entry.clone());
+        self.buffer.push(entry);
+    }
+
+    /// Passes a line to followers only.
+    pub fn notify(&self, entry: LogEntry) {
+        let _ = self.sender.send(entry);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LogEntry> {
+        self.sender.subscribe()
+    }
+}
+
+type ServiceKey = (String, String);
+
+/// A service's logs, plus what can be read without locking them
+#[derive(Debug, Clone, Default)]
+struct ServiceEntry {
+    logs: SharedServiceLogs,
+    /// Output bytes lost to line truncation and `\r` rewrites
+    bytes_dropped: Arc<AtomicU64>,
+}
+
+/// Batches of lines queued for the log file writer before capturing output
+/// waits on it
+const FILE_QUEUE_CAPACITY: usize = 1024;
+
+/// Work for the log file writer thread, done in the order it was queued
+#[derive(Debug)]
+enum FileOp {
+    /// Write the service's lines to this file from now on
+    Open(ServiceKey, LogFile),
+    Append(ServiceKey, Vec<LogEntry>),
+    Close(ServiceKey),
+}
+
+/// Logs of every service, kept out of the daemon state so that capturing
+/// output never waits on, or holds up, anything else. Each service has its
+/// own lock, so one chatty service does not slow down the others, and log
+/// files are written by a thread of their own so disk I/O never happens
+/// under a lock.
+#[derive(Debug, Clone)]
+pub struct LogStore {
+    services: Arc<Mutex<HashMap<ServiceKey, ServiceEntry>>>,
+    files: mpsc::Sender<FileOp>,
+}
+
+pub type SharedServiceLogs = Arc<Mutex<ServiceLogs>>;
+
+impl LogStore {
+    /// Creates an empty store and starts its log file writer thread.
+    pub fn new() -> std::io::Result<Self> {
+        let (files, ops) = mpsc::channel(FILE_QUEUE_CAPACITY);
+        std::thread::Builder::new()
+            .name("servinel-log-files".to_string())
+            .spawn(move || write_log_files(ops))?;
+        Ok(Self {
+            services: Arc::default(),
+            files,
+        })
+    }
+
+    fn entry(&self, app: &str, service: &str) -> ServiceEntry {
+        self.services
+            .lock()
+            .unwrap()
+            .entry((app.to_string(), service.to_string()))
+            .or_default()
+            .clone()
+    }
+
+    /// Logs of a service, created empty on first use.
+    pub fn service(&self, app: &str, service: &str) -> SharedServiceLogs {
+        self.entry(app, service).logs
+    }
+
+    pub fn entries(&self, app: &str, service: &str, run: Option<u32>, tail: Option<usize>) -> Vec<LogEntry> {
+        self.service(app, service).lock().unwrap().buffer.entries(run, tail)
+    }
+
+    pub fn bytes_dropped(&self, app: &str, service: &str) -> u64 {
+        self.entry(app, service).bytes_dropped.load(Ordering::Relaxed)
+    }
+
+    /// Counter of the bytes a service's output capture dropped
+    pub fn bytes_dropped_counter(&self, app: &str, service: &str) -> Arc<AtomicU64> {
+        self.entry(app, service).bytes_dropped
+    }
+
+    /// Sends a service's lines to `file` from now on, or to no file at all.
+    pub async fn set_file(&self, app: &str, service: &str, file: Option<LogFile>) {
+        let key = (app.to_string(), service.to_string());
+        let op = match file {
+            Some(file) => FileOp::Open(key, file),
+            None => FileOp::Close(key),
+        };
+        let _ = self.files.send(op).await;
+    }
+
+    /// Queues lines for the service's log file, if it has one. Waits while
+    /// the writer is behind, which slows down reading the service's output
+    /// rather than holding up anything else.
+    pub async fn write_file(&self, app: &str, service: &str, entries: Vec<LogEntry>) {
+        if entries.is_empty() {
+            return;
+        }
+        let key = (app.to_string(), service.to_string());
+        let _ = self.files.send(FileOp::Append(key, entries)).await;
+    }
+
+    pub async fn remove_app(&self, app: &str) {
+        let removed: Vec<ServiceKey> = {
+            let mut services = self.services.lock().unwrap();
+            let removed = services.keys().filter(|(owner, _)| owner == app).cloned().collect();
+            services.retain(|(owner, _), _| owner != app);
+            removed
+        };
+        for key in removed {
+            let _ = self.files.send(FileOp::Close(key)).await;
+        }
+    }
+}
+
+/// Body of the log file writer thread; ends once every `LogStore` is gone.
+fn write_log_files(mut ops: mpsc::Receiver<FileOp>) {
+    let mut files: HashMap<ServiceKey, LogFile> = HashMap::new();
+    while let Some(op) = ops.blocking_recv() {
+        match op {
+            FileOp::Open(key, file) => {
+                files.insert(key, file);
+            }
+            FileOp::Append(key, entries) => {
+                if let Some(file) = files.get_mut(&key) {
+                    for entry in &entries {
+                        file.append(entry);
+                    }
+                }
+            }
+            FileOp::Close(key) => {
+                files.remove(&key);
+            }
+        }
+    }
+}
+
+/// Splits raw process output into log lines. Bytes that are not UTF-8 are
+/// replaced rather than ending the capture, a `\r` rewrite (as used by
+/// progress bars) replaces what came before it on the line, and lines longer
+/// than `max_len` bytes are cut off with a marker.
+#[derive(Debug)]
+pub struct LineSplitter {
+    max_len: usize,
+    line: Vec<u8>,
+    /// Bytes of the current line beyond `max_len`
+    overflow: u64,
+    /// A `\r` was seen; it ends the line if `\n` follows, else rewrites it
+    pending_cr: bool,
+    /// Bytes discarded so far by truncation and `\r` rewrites
+    dropped: u64,
+}
+
+impl LineSplitter {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            max_len: max_len.max(1),
+            line: Vec::new(),
+            overflow: 0,
+            pending_cr: false,
+            dropped: 0,
+        }
+    }
+
+    /// Feeds a chunk of output, returning the lines it completes.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &byte in bytes {
+            if self.pending_cr && byte != b'\r' {
+                self.pending_cr = false;
+                if byte != b'\n' {
+                    self.dropped += self.line.len() as u64 + self.overflow;
+                    self.line.clear();
+                    self.overflow = 0;
+                }
+            }
+            match byte {
+                b'\n' => lines.push(self.take_line()),
+                b'\r' => self.pending_cr = true,
+                _ if self.line.len() < self.max_len => self.line.push(byte),
+                _ => self.overflow += 1,
+            }
+        }
+        lines
+    }
+
+    /// Returns the last line if the output did not end with a newline.
+    pub fn finish(&mut self) -> Option<String> {
+        self.pending_cr = false;
+        (!self.line.is_empty() || self.overflow > 0).then(|| self.take_line())
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn take_line(&mut self) -> String {
+        let mut line = String::from_utf8_lossy(&self.line).into_owned();
+        if self.overflow > 0 {
+            line.push_str(&format!(" … [truncated {} bytes]", self.overflow));
+            self.dropped += self.overflow;
+        }
+        self.line.clear();
+        self.overflow = 0;
+        line
+    }
+}
+
+/// Append-only JSON-lines log file for one service, rotated by size into
+/// `<name>.1` (newest) up to `<name>.<max_files - 1>` (oldest).
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: u32,
+    file: Option<File>,
+    size: u64,
+    /// Set after the first write error so a full disk does not flood the daemon log
+    failed: bool,
+}
+
+impl LogFile {
+    pub fn new(path: PathBuf, max_size: u64, max_files: u32) -> Self {
+        Self {
+            path,
+            max_size,
+            max_files: max_files.max(1),
+            file: None,
+            size: 0,
+            failed: false,
+        }
+    }
+
+    pub fn append(&mut self, entry: &LogEntry) {
+        if let Err(err) = self.try_append(entry) {
+            if !self.failed {
+                tracing::warn!(?err, path = ?self.path, "logs: cannot write log file");
+            }
+            self.failed = true;
+            self.file = None;
+        }
+    }
+
+    fn try_append(&mut self, entry: &LogEntry) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        if self.file.is_none() {
+            self.open()?;
+        }
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        if let Some(file) = &mut self.file {
+            file.write_all(&line)?;
+            self.size += line.len() as u64;
+        }
+        self.failed = false;
+        Ok(())
+    }
+
+    fn open(&mut self) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file = None;
+        if self.max_files == 1 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.max_files - 1).rev() {
+                let from = rotated_path(&self.path, index);
+                if from.exists() {
+                    std::fs::rename(from, rotated_path(&self.path, index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        self.open()
+    }
+}
+
+fn rotated_path(path: &Path, index: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{index}"));
+    PathBuf::from(name)
+}
+
+/// Reads a service's log file and its rotated predecessors, oldest first,
+/// keeping only entries of `run` when given and then the last `tail`. Lines
+/// that do not parse, such as one cut short by a crash, are skipped.
+pub fn read_log_files(
+    path: &Path,
+    max_files: u32,
+    run: Option<u32>,
+    tail: Option<usize>,
+) -> std::io::Result<Vec<LogEntry>> {
+    if tail == Some(0) {
+        return Ok(Vec::new());
+    }
+    let mut entries = VecDeque::new();
+    let files = (1..max_files.max(1))
+        .rev()
+        .map(|index| rotated_path(path, index))
+        .chain(std::iter::once(path.to_path_buf()));
+    for file in files {
+        let file = match File::open(&file) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        for line in BufReader::new(file).lines() {
+            let Ok(entry) = serde_json::from_str::<LogEntry>(&line?) else {
+                continue;
+            };
+            if run.is_some_and(|run| run != entry.run) {
+                continue;
+            }
+            if tail.is_some_and(|tail| entries.len() == tail) {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+    }
+    Ok(entries.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(run: u32, line: &str) -> LogEntry {
+        LogEntry {
+            timestamp: Utc::now(),
+            stream: LogStream::Stdout,
+            line: line.to_string(),
+            run,
+            source_timestamp: None,
+        }
+    }
+
+    fn lines(entries: &[LogEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.line.as_str()).collect()
+    }
+
+    fn timestamp_of(line: &str) -> Option<DateTime<Utc>> {
+        extract_timestamp(&Regex::new(r"^\[([^\]]+)\]").unwrap(), line)
+    }
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn extracts_rfc3339_and_offset_timestamps() {
+        assert_eq!(timestamp_of("[2024-05-01T10:00:00.250Z] up"), Some(utc("2024-05-01T10:00:00.250Z")));
+        assert_eq!(timestamp_of("[2024-05-01 12:00:00+02:00] up"), Some(utc("2024-05-01T10:00:00Z")));
+        assert_eq!(timestamp_of("[2024-05-01T12:00:00.5+0200] up"), Some(utc("2024-05-01T10:00:00.5Z")));
+    }
+
+    #[test]
+    fn reads_timestamps_without_offset_as_local_time() {
+        let naive = NaiveDateTime::parse_from_str("2024-05-01 10:00:00.125", "%Y-%m-%d %H:%M:%S%.f").unwrap();
+        let expected = Local.from_local_datetime(&naive).earliest().unwrap().with_timezone(&Utc);
+        assert_eq!(timestamp_of("[2024-05-01 10:00:00.125] up"), Some(expected));
+        assert_eq!(timestamp_of("[2024-05-01T10:00:00.125] up"), Some(expected));
+    }
+
+    #[test]
+    fn tells_epoch_seconds_from_milliseconds() {
+        let expected = utc("2023-11-14T22:13:20.250Z");
+        assert_eq!(timestamp_of("[1700000000.25] up"), Some(expected));
+        assert_eq!(timestamp_of("[1700000000250] up"), Some(expected));
+        // Just under the cut-off is still seconds, far in the future
+        assert_eq!(timestamp_of("[99999999999] up"), DateTime::from_timestamp(99_999_999_999, 0));
+        assert_eq!(timestamp_of("[100000000000] up"), DateTime::from_timestamp(100_000_000, 0));
+    }
+
+    #[test]
+    fn ignores_lines_without_a_timestamp() {
+        assert_eq!(timestamp_of("no brackets"), None);
+        assert_eq!(timestamp_of("[not a time] up"), None);
+        let no_group = Regex::new(r"^\[[^\]]+\]").unwrap();
+        assert_eq!(extract_timestamp(&no_group, "[2024-05-01T10:00:00Z] up"), None);
+    }
+
+    #[test]
+    fn reads_timestamps_written_as_seconds_or_rfc3339() {
+        let legacy: LogEntry =
+            serde_json::from_str(r#"{"timestamp":1700000000,"stream":"Stdout","line":"old"}"#).unwrap();
+        assert_eq!(legacy.timestamp, utc("2023-11-14T22:13:20Z"));
+        assert_eq!(legacy.run, 0);
+
+        let current = entry(2, "new");
+        let written = serde_json::to_string(&current).unwrap();
+        let read: LogEntry = serde_json::from_str(&written).unwrap();
+        assert_eq!(read.timestamp, current.timestamp);
+        assert_eq!(read.run, 2);
+    }
+
+    #[test]
+    fn splits_lines_across_chunks() {
+        let mut splitter = LineSplitter::new(100);
+        assert_eq!(splitter.push(b"one\ntw"), vec!["one"]);
+        assert_eq!(splitter.push(b"o\r\nthree\n"), vec!["two", "three"]);
+        assert_eq!(splitter.push(b"four"), Vec::<String>::new());
+        assert_eq!(splitter.finish().as_deref(), Some("four"));
+        assert_eq!(splitter.finish(), None);
+        assert_eq!(splitter.dropped(), 0);
+    }
+
+    #[test]
+    fn keeps_the_last_rewrite_of_a_line() {
+        let mut splitter = LineSplitter::new(100);
+        assert_eq!(splitter.push(b"10%\r50%\r"), Vec::<String>::new());
+        assert_eq!(splitter.push(b"100%\n"), vec!["100%"]);
+        assert_eq!(splitter.dropped(), 6);
+    }
+
+    #[test]
+    fn truncates_long_lines() {
+        let mut splitter = LineSplitter::new(4);
+        assert_eq!(splitter.push(b"abcdefgh\nok\n"), vec!["abcd … [truncated 4 bytes]", "ok"]);
+        assert_eq!(splitter.dropped(), 4);
+    }
+
+    #[test]
+    fn replaces_invalid_utf8() {
+        let mut splitter = LineSplitter::new(100);
+        assert_eq!(splitter.push(b"a\xffb\n"), vec!["a\u{fffd}b"]);
+    }
+
+    #[test]
+    fn caps_each_run_separately() {
+        let mut buffer = LogBuffer::new(2, 3);
+        buffer.start_run(1);
+        for line in ["a", "b", "c"] {
+            buffer.push(entry(1, line));
+        }
+        buffer.push(entry(2, "d"));
+        assert_eq!(lines(&buffer.entries(Some(1), None)), ["b", "c"]);
+        assert_eq!(lines(&buffer.entries(Some(2), None)), ["d"]);
+        assert_eq!(lines(&buffer.entries(None, None)), ["b", "c", "d"]);
+        assert_eq!(lines(&buffer.entries(None, Some(2))), ["c", "d"]);
+        assert_eq!(buffer.current_run(), Some(2));
+    }
+
+    #[test]
+    fn drops_the_oldest_runs() {
+        let mut buffer = LogBuffer::new(10, 2);
+        for run in 1..=3 {
+            buffer.push(entry(run, &format!("run {run}")));
+        }
+        assert_eq!(lines(&buffer.entries(None, None)), ["run 2", "run 3"]);
+        // A late line from a run that is gone is discarded
+        buffer.push(entry(1, "late"));
+        assert_eq!(lines(&buffer.entries(None, None)), ["run 2", "run 3"]);
+        // Starting a run that is not newer changes nothing
+        buffer.start_run(2);
+        assert_eq!(buffer.current_run(), Some(3));
     }
 }
@@ -19,7 +19,6 @@ async fn main() {
 
     let cli = cli::Cli::parse();
     if let Err(err) = cli::execute(cli).await {
-        eprintln!("Error: {err}");
-        std::process::exit(1);
+        std::process::exit(cli::report_error(&err));
     }
 }
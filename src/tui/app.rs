@@ -29,6 +29,8 @@ pub struct TuiApp {
     pub selected_app: usize,
     pub selected_service: usize,
     pub logs: Vec<LogLine>,
+    /// Show the logs of the selected service's previous run instead of the current one
+    pub previous_run: bool,
     pub system_cpu: f32,
     pub system_memory_used: u64,
     pub system_memory_total: u64,
@@ -69,6 +71,7 @@ impl Default for TuiApp {
             selected_app: 0,
             selected_service: 0,
             logs: Vec::new(),
+            previous_run: false,
             system_cpu: 0.0,
             system_memory_used: 0,
             system_memory_total: 0,
@@ -138,24 +141,24 @@ impl TuiApp {
     }
 
     pub fn next_service(&mut self) {
-        if let Some(app) = self.apps.get(self.selected_app) {
-            if !app.services.is_empty() {
-                self.selected_service = (self.selected_service + 1) % app.services.len();
-                self.reset_scroll();
-            }
+        if let Some(app) = self.apps.get(self.selected_app)
+            && !app.services.is_empty()
+        {
+            self.selected_service = (self.selected_service + 1) % app.services.len();
+            self.reset_scroll();
         }
     }
 
     pub fn prev_service(&mut self) {
-        if let Some(app) = self.apps.get(self.selected_app) {
-            if !app.services.is_empty() {
-                if self.selected_service == 0 {
-                    self.selected_service = app.services.len() - 1;
-                } else {
-                    self.selected_service -= 1;
-                }
-                self.reset_scroll();
+        if let Some(app) = self.apps.get(self.selected_app)
+            && !app.services.is_empty()
+        {
+            if self.selected_service == 0 {
+                self.selected_service = app.services.len() - 1;
+            } else {
+                self.selected_service -= 1;
             }
+            self.reset_scroll();
         }
     }
 
@@ -227,6 +230,11 @@ impl TuiApp {
         }
     }
 
+    pub fn toggle_previous_run(&mut self) {
+        self.previous_run = !self.previous_run;
+        self.autoscroll = true;
+    }
+
     pub fn scroll_to_top(&mut self) {
         self.scroll = 0;
         self.autoscroll = false;
@@ -264,13 +272,14 @@ impl TuiApp {
             return false;
         }
         let names: Vec<String> = self.apps.iter().map(|a| a.app_name.clone()).collect();
-        if let Some(idx) = Self::tab_index_at(column, area, &names) {
-            if idx < self.apps.len() && idx != self.selected_app {
-                self.selected_app = idx;
-                self.selected_service = 0;
-                self.reset_scroll();
-                return true;
-            }
+        if let Some(idx) = Self::tab_index_at(column, area, &names)
+            && idx < self.apps.len()
+            && idx != self.selected_app
+        {
+            self.selected_app = idx;
+            self.selected_service = 0;
+            self.reset_scroll();
+            return true;
         }
         false
     }
@@ -287,12 +296,13 @@ impl TuiApp {
             .get(self.selected_app)
             .map(|app| app.services.iter().map(|s| s.name.clone()).collect())
             .unwrap_or_default();
-        if let Some(idx) = Self::tab_index_at(column, area, &names) {
-            if idx < names.len() && idx != self.selected_service {
-                self.selected_service = idx;
-                self.reset_scroll();
-                return true;
-            }
+        if let Some(idx) = Self::tab_index_at(column, area, &names)
+            && idx < names.len()
+            && idx != self.selected_service
+        {
+            self.selected_service = idx;
+            self.reset_scroll();
+            return true;
         }
         false
     }
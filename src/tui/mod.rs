@@ -10,8 +10,11 @@ use ratatui::backend::CrosstermBackend;
 use ratatui::{CompletedFrame, Terminal};
 
 use crate::error::Result;
-use crate::ipc::client::{request_response, stream_logs};
-use crate::ipc::protocol::{Request, Response, ServiceSelector};
+use crate::ipc::client::{Call, Connection};
+use crate::ipc::protocol::{
+    ClientContext, Event as DaemonEvent, LogRun, Request, Response, ServiceSelector, StatusSnapshot, Topic,
+    LOG_TIME_FORMAT,
+};
 use crate::tui::app::TuiApp;
 
 mod app;
@@ -27,14 +30,17 @@ pub async fn run() -> Result<()> {
 
     let (tx, rx) = mpsc::channel();
     std::thread::spawn(move || loop {
-        if let Ok(event) = crossterm::event::read() {
-            if tx.send(event).is_err() {
-                break;
-            }
+        if let Ok(event) = crossterm::event::read()
+            && tx.send(event).is_err()
+        {
+            break;
         }
     });
 
     let mut app = TuiApp::default();
+    let mut connection: Option<Connection> = None;
+    let mut status_events: Option<Call> = None;
+    let mut status: Option<StatusSnapshot> = None;
     let mut interval = tokio::time::interval(Duration::from_millis(50));
     let mut should_quit = false;
 
@@ -43,6 +49,16 @@ pub async fn run() -> Result<()> {
 
     while !should_quit {
         interval.tick().await;
+        if connection.as_ref().is_none_or(Connection::is_closed) {
+            connection = Connection::open().await.ok();
+            status_events = connection.as_ref().and_then(|connection| {
+                connection
+                    .call(Request::Subscribe {
+                        topics: vec![Topic::Status],
+                    })
+                    .ok()
+            });
+        }
         while let Ok(event) = rx.try_recv() {
             match event {
                 Event::Key(key) => {
@@ -71,39 +87,40 @@ pub async fn run() -> Result<()> {
                         KeyCode::PageUp => app.page_up(),
                         KeyCode::PageDown => app.page_down(),
                         KeyCode::Home => app.scroll_to_top(),
+                        KeyCode::Char('p') => app.toggle_previous_run(),
                         KeyCode::End => app.scroll_to_bottom(),
                         KeyCode::Char('s') => {
                             if let (Some(app_name), Some(service)) =
                                 (app.selected_app_name(), app.selected_service_name())
                             {
-                                let _ = request_response(&Request::Start {
+                                send_action(connection.as_ref(), Request::Start {
                                     file: None,
+                                    overrides: Vec::new(),
                                     app: Some(app_name),
                                     selector: ServiceSelector::Service(service),
-                                })
-                                .await;
+                                    client: Some(ClientContext::capture()),
+                                });
                             }
                         }
                         KeyCode::Char('x') => {
                             if let (Some(app_name), Some(service)) =
                                 (app.selected_app_name(), app.selected_service_name())
                             {
-                                let _ = request_response(&Request::Stop {
+                                send_action(connection.as_ref(), Request::Stop {
                                     app: Some(app_name),
                                     selector: ServiceSelector::Service(service),
-                                })
-                                .await;
+                                    timeout: None,
+                                });
                             }
                         }
                         KeyCode::Char('r') => {
                             if let (Some(app_name), Some(service)) =
                                 (app.selected_app_name(), app.selected_service_name())
                             {
-                                let _ = request_response(&Request::Restart {
+                                send_action(connection.as_ref(), Request::Restart {
                                     app: Some(app_name),
                                     selector: ServiceSelector::Service(service),
-                                })
-                                .await;
+                                });
                             }
                         }
                         _ => {}
@@ -146,14 +163,12 @@ pub async fn run() -> Result<()> {
                         MouseEventKind::Drag(crossterm::event::MouseButton::Left) => {
                             app.update_selection(mouse.column, mouse.row);
                         }
-                        MouseEventKind::Up(crossterm::event::MouseButton::Left) => {
-                            if app.selecting {
-                                app.finish_selection();
-                                if let Some(text) = app.get_selected_text() {
-                                    if let Ok(mut clipboard) = arboard::Clipboard::new() {
-                                        let _ = clipboard.set_text(text);
-                                    }
-                                }
+                        MouseEventKind::Up(crossterm::event::MouseButton::Left) if app.selecting => {
+                            app.finish_selection();
+                            if let Some(text) = app.get_selected_text()
+                                && let Ok(mut clipboard) = arboard::Clipboard::new()
+                            {
+                                let _ = clipboard.set_text(text);
                             }
                         }
                         _ => {}
@@ -163,8 +178,12 @@ pub async fn run() -> Result<()> {
             }
         }
 
-        refresh_status(&mut app).await?;
-        refresh_logs(&mut app).await?;
+        if let Some(events) = &mut status_events {
+            apply_status_events(&mut app, events, &mut status);
+        }
+        if let Some(connection) = &connection {
+            refresh_logs(&mut app, connection).await;
+        }
 
         let completed = terminal.draw(|frame| ui::draw(frame, &mut app))?;
         capture_screen_buffer(&mut app, &completed);
@@ -174,49 +193,68 @@ pub async fn run() -> Result<()> {
     Ok(())
 }
 
-async fn refresh_status(app: &mut TuiApp) -> Result<()> {
-    let request = Request::Status {
-        app: None,
-        selector: ServiceSelector::All,
-    };
-    let response = tokio::time::timeout(Duration::from_millis(500), request_response(&request)).await;
-    let Ok(Ok(Response::StatusSnapshot(snapshot))) = response else {
-        return Ok(());
-    };
-    app.update_snapshot(
-        snapshot.apps,
-        snapshot.system_cpu,
-        snapshot.system_memory_used,
-        snapshot.system_memory_total,
-    );
-    Ok(())
+/// Starts, stops or restarts services in the background, so the dashboard
+/// keeps updating while services shut down.
+fn send_action(connection: Option<&Connection>, request: Request) {
+    if let Some(connection) = connection.cloned() {
+        tokio::spawn(async move {
+            let _ = connection.request(request).await;
+        });
+    }
+}
+
+/// Applies the status changes pushed by the daemon since the last frame.
+fn apply_status_events(app: &mut TuiApp, events: &mut Call, status: &mut Option<StatusSnapshot>) {
+    let mut changed = false;
+    while let Some(response) = events.try_next() {
+        let Response::Event(event) = response else {
+            continue;
+        };
+        match (*event, status.as_mut()) {
+            (DaemonEvent::Snapshot(snapshot), _) => *status = Some(snapshot),
+            (DaemonEvent::Delta(delta), Some(status)) => status.apply(delta),
+            _ => continue,
+        }
+        changed = true;
+    }
+    if changed && let Some(status) = status {
+        app.update_snapshot(
+            status.apps.clone(),
+            status.system_cpu,
+            status.system_memory_used,
+            status.system_memory_total,
+        );
+    }
 }
 
-async fn refresh_logs(app: &mut TuiApp) -> Result<()> {
+async fn refresh_logs(app: &mut TuiApp, connection: &Connection) {
     let (app_name, service) = match (app.selected_app_name(), app.selected_service_name()) {
         (Some(app_name), Some(service)) => (app_name, service),
         _ => {
             app.logs.clear();
-            return Ok(());
+            return;
         }
     };
 
     let request = Request::Logs {
-        app: Some(app_name.clone()),
-        selector: ServiceSelector::Service(service.clone()),
+        app: Some(app_name),
+        selector: ServiceSelector::Service(service),
         follow: false,
         tail: Some(200),
         merged: true,
+        run: app.previous_run.then_some(LogRun::Previous),
     };
 
     let mut log_lines = Vec::new();
     let response = tokio::time::timeout(
         Duration::from_millis(600),
-        stream_logs(&request, |chunk| {
-            let time = chrono::DateTime::from_timestamp(chunk.entry.timestamp as i64, 0)
-                .map(|dt| dt.with_timezone(&chrono::Local))
-                .unwrap_or_default();
-            let time_str = time.format("%Y-%m-%d %H:%M:%S").to_string();
+        connection.stream_logs(request, |chunk| {
+            let time_str = chunk
+                .entry
+                .timestamp
+                .with_timezone(&chrono::Local)
+                .format(LOG_TIME_FORMAT)
+                .to_string();
             
             log_lines.push(crate::tui::app::LogLine {
                 timestamp: time_str,
@@ -226,14 +264,9 @@ async fn refresh_logs(app: &mut TuiApp) -> Result<()> {
         }),
     )
     .await;
-    if response.is_err() {
-        return Ok(());
+    if let Ok(Ok(())) = response {
+        app.logs = log_lines;
     }
-    if response.unwrap().is_err() {
-        return Ok(());
-    }
-    app.logs = log_lines;
-    Ok(())
 }
 
 fn restore_terminal(mut terminal: Terminal<CrosstermBackend<Stdout>>) -> Result<()> {
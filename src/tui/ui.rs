@@ -6,6 +6,15 @@ use ratatui::Frame;
 
 use crate::tui::app::TuiApp;
 
+fn status_color(status: &str) -> Color {
+    match status {
+        "running" => Color::Green,
+        "starting" | "waiting" => Color::Yellow,
+        "unhealthy" | "exited" | "failed" => Color::Red,
+        _ => Color::DarkGray,
+    }
+}
+
 pub fn draw(frame: &mut Frame<'_>, app: &mut TuiApp) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
@@ -82,8 +91,9 @@ pub fn draw(frame: &mut Frame<'_>, app: &mut TuiApp) {
         ]));
     }
     
+    let log_title = if app.previous_run { "Logs (previous run)" } else { "Logs" };
     let logs = Paragraph::new(log_lines)
-        .block(Block::default().borders(Borders::ALL).title("Logs"))
+        .block(Block::default().borders(Borders::ALL).title(log_title))
         .scroll((effective_scroll as u16, scroll_x));
     frame.render_widget(logs, log_area);
 
@@ -126,7 +136,10 @@ pub fn draw(frame: &mut Frame<'_>, app: &mut TuiApp) {
         vec![
             Line::from(vec![
                 Span::raw("Status: "),
-                Span::styled(service.status, Style::default().fg(Color::Green)),
+                Span::styled(
+                    service.status.clone(),
+                    Style::default().fg(status_color(&service.status)),
+                ),
             ]),
             Line::from(format!(
                 "PID: {}",
@@ -144,11 +157,9 @@ pub fn draw(frame: &mut Frame<'_>, app: &mut TuiApp) {
             )),
             Line::from(format!(
                 "Exit: {}",
-                service
-                    .exit_code
-                    .map(|c| c.to_string())
-                    .unwrap_or_else(|| "-".to_string())
+                service.exit_description()
             )),
+            Line::from(format!("Restarts: {}", service.restart_count)),
             Line::from(format!("CPU: {:.2}%", service.metrics.cpu)),
             Line::from(format!(
                 "Memory: {:.1} MB",
@@ -196,7 +207,7 @@ pub fn draw(frame: &mut Frame<'_>, app: &mut TuiApp) {
     frame.render_widget(mem_gauge, pie_chunks[1]);
 
     let help = Paragraph::new(
-        "Keys: Tab/S-Tab apps  ←/→ services  ↑/↓ scroll  s start  x stop  r restart  q quit  │  drag to select & copy",
+        "Keys: Tab/S-Tab apps  ←/→ services  ↑/↓ scroll  s start  x stop  r restart  p previous run  q quit  │  drag to select & copy",
     )
     .block(Block::default().borders(Borders::ALL).title("Help"));
     frame.render_widget(help, chunks[3]);
@@ -1,8 +1,11 @@
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use crate::error::{Result, ServinelError};
 
 pub const DEFAULT_COMPOSE_FILE: &str = "servinel-compose.yaml";
+/// Merged on top of the compose file next to it when only one file is given
+pub const OVERRIDE_COMPOSE_FILE: &str = "servinel-compose.override.yaml";
 
 pub fn find_compose_file() -> Result<Option<PathBuf>> {
     let cwd = std::env::current_dir()?;
@@ -16,7 +19,7 @@ pub fn find_compose_file() -> Result<Option<PathBuf>> {
 
 pub fn require_compose_file(path: Option<PathBuf>) -> Result<PathBuf> {
     if let Some(path) = path {
-        return Ok(normalize_path(path)?);
+        return normalize_path(path);
     }
 
     match find_compose_file()? {
@@ -35,16 +38,98 @@ pub fn normalize_path(path: PathBuf) -> Result<PathBuf> {
     }
 }
 
-pub fn app_data_dir() -> Result<PathBuf> {
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// Root of all servinel data: `$SERVINEL_HOME`, or `~/.servinel`.
+pub fn servinel_home() -> Result<PathBuf> {
+    if let Some(home) = std::env::var_os("SERVINEL_HOME").filter(|home| !home.is_empty()) {
+        return Ok(PathBuf::from(home));
+    }
     let home = std::env::var("HOME")
         .map_err(|_| std::io::Error::new(std::io::ErrorKind::NotFound, "HOME not set"))?;
     Ok(Path::new(&home).join(".servinel"))
 }
 
+/// Namespace selected with `--namespace` (passed on via `SERVINEL_NAMESPACE`).
+pub fn namespace() -> Result<Option<String>> {
+    match std::env::var("SERVINEL_NAMESPACE") {
+        Ok(name) if !name.is_empty() && name != DEFAULT_NAMESPACE => {
+            validate_namespace(&name).map_err(ServinelError::Usage)?;
+            Ok(Some(name))
+        }
+        _ => Ok(None),
+    }
+}
+
+pub fn validate_namespace(name: &str) -> std::result::Result<(), String> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid namespace '{name}' (use letters, digits, '-' and '_')"
+        ))
+    }
+}
+
+/// Data directory of the current namespace. The default namespace lives in
+/// the root itself; others get `namespaces/<name>` with their own socket,
+/// state and daemon.
+pub fn app_data_dir() -> Result<PathBuf> {
+    Ok(namespace_dir(&servinel_home()?, namespace()?.as_deref()))
+}
+
+fn namespace_dir(home: &Path, namespace: Option<&str>) -> PathBuf {
+    match namespace {
+        Some(name) => home.join("namespaces").join(name),
+        None => home.to_path_buf(),
+    }
+}
+
+/// Every namespace that has a data directory, the default one first.
+pub fn list_namespaces() -> Result<Vec<(String, PathBuf)>> {
+    let home = servinel_home()?;
+    let mut namespaces = vec![(DEFAULT_NAMESPACE.to_string(), home.clone())];
+    let mut named = Vec::new();
+    if let Ok(entries) = std::fs::read_dir(home.join("namespaces")) {
+        for entry in entries.flatten() {
+            if entry.path().is_dir() {
+                named.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+    }
+    named.sort();
+    for name in named {
+        let dir = namespace_dir(&home, Some(&name));
+        namespaces.push((name, dir));
+    }
+    Ok(namespaces)
+}
+
 pub fn socket_path() -> Result<PathBuf> {
     Ok(app_data_dir()?.join("servinel.sock"))
 }
 
+pub fn pidfile_path() -> Result<PathBuf> {
+    Ok(app_data_dir()?.join(PIDFILE_NAME))
+}
+
+pub const PIDFILE_NAME: &str = "servinel.pid";
+
+/// Bearer token for the HTTP API, created by the daemon when the API is on
+pub fn http_token_path() -> Result<PathBuf> {
+    Ok(app_data_dir()?.join("http-token"))
+}
+
+/// Where running services write their stdout and stderr, which the daemon
+/// reads into their logs
+pub fn output_dir() -> Result<PathBuf> {
+    Ok(app_data_dir()?.join("output"))
+}
+
 pub fn ensure_app_dir() -> Result<PathBuf> {
     let path = app_data_dir()?;
     if !path.exists() {
@@ -52,3 +137,146 @@ pub fn ensure_app_dir() -> Result<PathBuf> {
     }
     Ok(path)
 }
+
+/// Parses durations like `500ms`, `10s`, `2m`, `1h`; a bare number is seconds.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let secs = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+/// Parses sizes like `512KB`, `10MB` or `1GB` (powers of 1024); a bare number is bytes.
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return None,
+    };
+    number.checked_mul(multiplier)
+}
+
+/// Parses `SIGTERM`, `TERM`, `term` or a signal number.
+pub fn parse_signal(value: &str) -> Option<i32> {
+    let value = value.trim();
+    if let Ok(number) = value.parse::<i32>() {
+        return (number > 0).then_some(number);
+    }
+    let upper = value.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNALS
+        .iter()
+        .find(|(candidate, _)| *candidate == name)
+        .map(|(_, number)| *number)
+}
+
+pub fn signal_name(signal: i32) -> String {
+    SIGNALS
+        .iter()
+        .find(|(_, number)| *number == signal)
+        .map(|(name, _)| format!("SIG{name}"))
+        .unwrap_or_else(|| format!("signal {signal}"))
+}
+
+const SIGNALS: [(&str, i32); 9] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("TERM", libc::SIGTERM),
+    ("CONT", libc::SIGCONT),
+    ("WINCH", libc::SIGWINCH),
+];
+
+pub fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if millis.is_multiple_of(1000) {
+        format!("{}s", millis / 1000)
+    } else {
+        format!("{millis}ms")
+    }
+}
+
+/// Candidates close enough to `name` to be what was meant, best first: the
+/// ones within a few typos of it, or that start with or contain it.
+pub fn similar_names<'a>(name: &str, candidates: &'a [String]) -> Vec<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    let mut matches: Vec<(usize, &str)> = candidates
+        .iter()
+        .filter_map(|candidate| {
+            let distance = edit_distance(name, candidate);
+            let close = distance <= max_distance || (!name.is_empty() && candidate.contains(name));
+            close.then_some((distance, candidate.as_str()))
+        })
+        .collect();
+    matches.sort();
+    matches.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+/// Levenshtein distance between two strings, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn suggests_names_within_a_few_typos() {
+        let candidates = names(&["postgres", "redis", "worker"]);
+        assert_eq!(similar_names("postgre", &candidates), ["postgres"]);
+        assert_eq!(similar_names("rdis", &candidates), ["redis"]);
+        assert!(similar_names("frontend", &candidates).is_empty());
+    }
+
+    #[test]
+    fn suggests_names_containing_the_input() {
+        let candidates = names(&["api-gateway", "api", "web"]);
+        assert_eq!(similar_names("api", &candidates), ["api", "api-gateway"]);
+        assert_eq!(similar_names("gateway", &candidates), ["api-gateway"]);
+    }
+
+    #[test]
+    fn orders_suggestions_by_distance() {
+        let candidates = names(&["workers", "worker"]);
+        assert_eq!(similar_names("worke", &candidates), ["worker", "workers"]);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+}